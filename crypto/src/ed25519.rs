@@ -88,7 +88,7 @@ impl Ed25519PrivateKey {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct Ed25519PublicKey(DalekPublicKey);
 
 impl PublicKey for Ed25519PublicKey {
@@ -112,14 +112,24 @@ impl Block {
         self.previous_block_hash.is_none() && self.height == 1
     }
 
+    /// The previous block hash is used as signage data for the plot filter
+    ///
+    /// The quality strings are looked up first, only the proof of the best one
+    /// is then read from the plot.
     pub fn try_prove(&mut self, prover: &Prover) -> Result<()> {
-        let proofs = prover.retrieve_all_proofs(&self.hash)?;
-        if proofs.len() > 0 {
-            self.proof = Some(proofs[0].clone());
-            return Ok(());
-        } else {
-            return Err(BlockError::NoProofFound.into());
-        }
+        let signage_data = self.previous_block_hash.as_deref().unwrap_or_default();
+        let best = prover
+            .get_quality_string(&self.hash, signage_data)?
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.cmp(b.1))
+            .map(|(index, _)| index)
+            .ok_or(BlockError::NoProofFound)?;
+        let proof = prover
+            .get_full_proof(&self.hash, signage_data, best)?
+            .ok_or(BlockError::NoProofFound)?;
+        self.proof = Some(proof);
+        Ok(())
     }
 
     fn calculate_hash(&self) -> Result<BlockHash> {
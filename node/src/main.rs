@@ -1,8 +1,17 @@
-use std::fs::create_dir_all;
-use std::path::Path;
+mod plot_manager;
 
+use std::convert::TryInto;
+use std::fs::{create_dir_all, read_dir};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
+use borsh::BorshDeserialize;
 use console::style;
 use console::truncate_str;
 use console::Emoji;
@@ -11,23 +20,29 @@ use dialoguer::theme::ColorfulTheme;
 use dialoguer::Input;
 use dialoguer::Select;
 use log::*;
+use plot_manager::{PlotManager, PlotQueue, QueueLock};
 use rand::Rng;
 use rand::{rngs::OsRng, RngCore};
 use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode};
 use spaceframe_crypto::ed25519::Ed25519KeyPair;
+use spaceframe_crypto::ed25519::Ed25519PublicKey;
 use spaceframe_crypto::traits::Keypair;
 use spaceframe_ledger::account::Address;
 use spaceframe_ledger::error::BlockError;
 use spaceframe_ledger::ledger::Ledger;
 use spaceframe_ledger::transaction::Tx;
+use spaceframe_pospace::config::PlottingConfig;
 use spaceframe_pospace::constants::PARAM_BC;
 use spaceframe_pospace::constants::PARAM_EXT;
 use spaceframe_pospace::core::PoSpace;
 use spaceframe_pospace::fx_calculator::matching_naive;
 use spaceframe_pospace::fx_calculator::FxCalculator;
 use spaceframe_pospace::fx_calculator::Match;
-use spaceframe_pospace::proofs::Proof;
-use spaceframe_pospace::proofs::Prover;
+use spaceframe_pospace::harvester::Harvester;
+use spaceframe_pospace::plot::PLOT_EXTENSION;
+use spaceframe_pospace::plot_id::PlotId;
+use spaceframe_pospace::progress::ProgressSink;
+use spaceframe_pospace::proofs::{Proof, Prover};
 use spaceframe_pospace::storage::PlotEntry;
 use spaceframe_pospace::verifier::Verifier;
 use spaceframe_storage::keypair::read_all_keypair;
@@ -49,18 +64,58 @@ enum Command {
     Init {
         #[structopt(short = "k")]
         space: usize,
+
+        /// Directory for the intermediate files
+        #[structopt(short = "t", long = "tmp-dir", default_value = "data")]
+        tmp_dir: PathBuf,
+
+        /// Directory for the compressed tables, defaults to the temporary directory
+        #[structopt(long = "tmp2-dir")]
+        tmp2_dir: Option<PathBuf>,
+
+        /// Directory where the finished plot is stored
+        #[structopt(short = "d", long = "final-dir", default_value = "data")]
+        final_dir: PathBuf,
+
+        /// Memory budget of the plotting in MiB
+        #[structopt(short = "m", long = "memory", default_value = "1024")]
+        memory: usize,
+
+        /// Number of threads used by the plotting, defaults to the number of CPUs
+        #[structopt(long = "threads")]
+        threads: Option<usize>,
+
+        /// Number of buckets used to sort the tables
+        #[structopt(long = "buckets", default_value = "128")]
+        buckets: usize,
+
+        /// Farmer public key in hexadecimal, defaults to the first stored keypair
+        #[structopt(long = "farmer-key")]
+        farmer_key: Option<String>,
+
+        /// Pool public key in hexadecimal, defaults to the farmer public key
+        #[structopt(long = "pool-key")]
+        pool_key: Option<String>,
     },
 
     /// Used for benchmarking the proof of space proving
     Prove {
         #[structopt(short = "k")]
         space: usize,
+
+        /// Challenge in hexadecimal, defaults to a random challenge
+        #[structopt(short = "c", long = "challenge")]
+        challenge: Option<String>,
     },
 
     /// Used for benchmarking the proof of space verifing
     Verify {
         #[structopt(short = "k")]
         space: usize,
+
+        /// Challenge in hexadecimal, defaults to a random challenge
+        #[structopt(short = "c", long = "challenge")]
+        challenge: Option<String>,
     },
 
     /// Used for benchmarking the proof of space matching functions
@@ -78,180 +133,340 @@ enum Command {
         #[structopt(short = "k")]
         k: usize,
     },
+
+    /// Answer random challenges with every plot of the directories
+    Harvest {
+        /// Directories containing plots, plots added or removed later are picked up
+        #[structopt(short = "d", long = "plot-dir", default_value = "data")]
+        plot_dirs: Vec<PathBuf>,
+
+        /// Number of plots of the same directory read at the same time
+        #[structopt(long = "disk-concurrency", default_value = "2")]
+        disk_concurrency: usize,
+
+        /// Seconds between two challenges
+        #[structopt(long = "interval", default_value = "10")]
+        interval: u64,
+    },
+
+    /// Queue plot jobs and run them
+    Queue {
+        #[structopt(subcommand)]
+        cmd: QueueCommand,
+    },
+
+    /// Manage the plots
+    Plots {
+        #[structopt(subcommand)]
+        cmd: PlotsCommand,
+    },
+}
+
+#[derive(StructOpt)]
+enum QueueCommand {
+    /// Add plot jobs to the queue
+    Add {
+        #[structopt(short = "k")]
+        space: usize,
+
+        /// Directory for the intermediate files
+        #[structopt(short = "t", long = "tmp-dir", default_value = "data")]
+        tmp_dir: PathBuf,
+
+        /// Directory where the finished plot is stored
+        #[structopt(short = "d", long = "final-dir", default_value = "data")]
+        final_dir: PathBuf,
+
+        /// Memory budget of each job in MiB
+        #[structopt(short = "m", long = "memory", default_value = "1024")]
+        memory: usize,
+
+        /// Farmer public key in hexadecimal, defaults to the first stored keypair
+        #[structopt(long = "farmer-key")]
+        farmer_key: Option<String>,
+
+        /// Pool public key in hexadecimal, defaults to the farmer public key
+        #[structopt(long = "pool-key")]
+        pool_key: Option<String>,
+
+        /// Number of plots to create
+        #[structopt(short = "n", long = "count", default_value = "1")]
+        count: usize,
+    },
+
+    /// Show the jobs and their status
+    List,
+
+    /// Cancel a queued or running job
+    Cancel { id: u64 },
+
+    /// Run the queued jobs until the queue is empty
+    Run {
+        /// Number of jobs running at the same time
+        #[structopt(short = "p", long = "parallel", default_value = "1")]
+        parallel: usize,
+
+        /// Seconds between the starts of two jobs
+        #[structopt(short = "s", long = "stagger", default_value = "0")]
+        stagger: u64,
+
+        /// Number of threads of each job, defaults to the CPUs shared between the jobs
+        #[structopt(long = "threads")]
+        threads: Option<usize>,
+    },
+}
+
+#[derive(StructOpt)]
+enum PlotsCommand {
+    /// Check the integrity of plots with random challenges
+    Check {
+        /// Plot file or directory of plots
+        #[structopt(default_value = "data")]
+        path: PathBuf,
+
+        /// Number of challenges issued against each plot
+        #[structopt(short = "n", long = "challenges", default_value = "30")]
+        challenges: usize,
+    },
 }
 
-const fn get_challenge(k: usize) -> [u8; 32] {
-    match k {
-        17 => [
-            95, 23, 106, 107, 81, 99, 43, 112, 157, 49, 246, 48, 199, 114, 163, 190, 160, 165, 251,
-            13, 92, 80, 240, 210, 241, 247, 44, 74, 94, 126, 245, 226,
-        ],
-        19 => [
-            140, 31, 177, 106, 121, 35, 250, 68, 109, 103, 251, 149, 126, 201, 224, 230, 37, 74,
-            247, 24, 146, 131, 28, 74, 17, 105, 126, 93, 105, 34, 222, 152,
-        ],
-        20 => [
-            8, 69, 62, 233, 63, 175, 0, 92, 104, 211, 47, 131, 61, 52, 7, 0, 19, 150, 63, 103, 88,
-            212, 133, 181, 140, 197, 12, 27, 33, 249, 33, 196,
-        ],
-        21 => [
-            154, 156, 38, 140, 105, 6, 177, 113, 168, 152, 154, 83, 173, 244, 200, 201, 218, 49,
-            102, 110, 98, 200, 99, 103, 187, 151, 182, 107, 149, 19, 244, 32,
-        ],
-        22 => [
-            95, 173, 51, 119, 88, 121, 172, 0, 127, 130, 7, 43, 153, 16, 149, 83, 31, 188, 77, 86,
-            226, 139, 33, 67, 232, 168, 112, 191, 24, 57, 130, 138,
-        ],
-        23 => [
-            140, 68, 99, 54, 232, 127, 7, 76, 16, 142, 107, 249, 168, 126, 107, 139, 8, 163, 255,
-            57, 54, 221, 139, 159, 175, 120, 246, 228, 68, 141, 187, 12,
-        ],
-        24 => [
-            144, 123, 11, 221, 104, 85, 239, 89, 29, 145, 216, 197, 80, 113, 141, 53, 10, 17, 88,
-            158, 221, 19, 230, 239, 110, 219, 195, 31, 174, 78, 214, 191,
-        ],
-        25 => [
-            144, 152, 10, 181, 73, 130, 35, 247, 217, 187, 27, 183, 205, 119, 253, 176, 144, 123,
-            122, 44, 23, 12, 87, 203, 241, 128, 6, 210, 32, 84, 254, 181,
-        ],
-        26 => [
-            29, 248, 68, 61, 42, 102, 28, 207, 224, 69, 99, 250, 122, 119, 0, 212, 242, 153, 185,
-            228, 86, 21, 149, 176, 59, 228, 126, 207, 63, 231, 66, 205,
-        ],
-        _ => [0u8; 32],
+/// Challenge given in hexadecimal, a random one when none is given
+fn parse_challenge(challenge: Option<&str>) -> Result<[u8; 32]> {
+    match challenge {
+        Some(challenge) => hex::decode(challenge)
+            .context("The challenge must be hexadecimal")?
+            .try_into()
+            .map_err(|_| anyhow!("The challenge must be 32 bytes long")),
+        None => {
+            let mut challenge = [0u8; 32];
+            OsRng.fill_bytes(&mut challenge);
+            Ok(challenge)
+        }
     }
 }
 
-fn get_proof(k: usize) -> Proof {
-    let plot_seed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
-    match k {
-        17 => Proof {
-            k,
-            plot_seed,
-            challenge: vec![
-                95, 23, 106, 107, 81, 99, 43, 112, 157, 49, 246, 48, 199, 114, 163, 190, 160, 165,
-                251, 13, 92, 80, 240, 210, 241, 247, 44, 74, 94, 126, 245, 226,
-            ],
-            x_values: vec![
-                46672, 123945, 25137, 96153, 44075, 57751, 43472, 53529, 118276, 26778, 54551,
-                55527, 78723, 98898, 37295, 73748, 37594, 122322, 29862, 39783, 78833, 90645,
-                51199, 16622, 107778, 16953, 124903, 85816, 69558, 119363, 68128, 85232, 116016,
-                73508, 125878, 45672, 115363, 5140, 73432, 94498, 85600, 21806, 36775, 113527,
-                104277, 109103, 25439, 35189, 66920, 101463, 16110, 49325, 83135, 73827, 25196,
-                93703, 77659, 128937, 7216, 54000, 111494, 73524, 104245, 58276,
-            ],
-        },
-        19 => Proof {
-            k,
-            plot_seed,
-            challenge: vec![
-                140, 31, 177, 106, 121, 35, 250, 68, 109, 103, 251, 149, 126, 201, 224, 230, 37,
-                74, 247, 24, 146, 131, 28, 74, 17, 105, 126, 93, 105, 34, 222, 152,
-            ],
-            x_values: vec![
-                326828, 226837, 467171, 19217, 189859, 503228, 137074, 152964, 179964, 476292,
-                54147, 35682, 485343, 456105, 252920, 2385, 494048, 407697, 49682, 426297, 147264,
-                349193, 163479, 251801, 317898, 159218, 144749, 60391, 397922, 393707, 152431,
-                338751, 251743, 380638, 470257, 492738, 4688, 119647, 418527, 424192, 415192,
-                219460, 32965, 149370, 371741, 139462, 217438, 403000, 273374, 427204, 329506,
-                174100, 436861, 49923, 208038, 285216, 313296, 126968, 417137, 73340, 422042,
-                470958, 502497, 239070,
-            ],
-        },
-        20 => Proof {
-            k,
-            plot_seed,
-            challenge: vec![
-                8, 69, 62, 233, 63, 175, 0, 92, 104, 211, 47, 131, 61, 52, 7, 0, 19, 150, 63, 103,
-                88, 212, 133, 181, 140, 197, 12, 27, 33, 249, 33, 196,
-            ],
-            x_values: vec![
-                477931, 578035, 153607, 334295, 663377, 837112, 46451, 594748, 519771, 918984,
-                588923, 590486, 69605, 154991, 152568, 322641, 301115, 493745, 789188, 871258,
-                748448, 69350, 504405, 434764, 646851, 816049, 565706, 350365, 96069, 677778,
-                491897, 51878, 83945, 289424, 263292, 872078, 908113, 157692, 320950, 655577,
-                314201, 641305, 128850, 317680, 49843, 789407, 776607, 934930, 659921, 145260,
-                796901, 78276, 1010292, 92290, 379240, 663933, 305439, 585537, 529340, 68460,
-                313166, 633025, 6583, 382909,
-            ],
-        },
-        22 => Proof {
-            k,
-            plot_seed,
-            challenge: vec![
-                95, 173, 51, 119, 88, 121, 172, 0, 127, 130, 7, 43, 153, 16, 149, 83, 31, 188, 77,
-                86, 226, 139, 33, 67, 232, 168, 112, 191, 24, 57, 130, 138,
-            ],
-            x_values: vec![
-                2206732, 1361892, 1111147, 2927325, 3187419, 1505932, 1665666, 2230025, 795391,
-                1040601, 1540975, 960787, 2309970, 389690, 3478858, 1351343, 1636294, 4063128,
-                955379, 3398243, 1785847, 1141308, 497087, 2984069, 3110479, 429643, 576518,
-                407472, 626260, 3625927, 335514, 1453218, 2257503, 80774, 2782590, 687786, 391252,
-                2687194, 2176126, 362765, 1776509, 2411109, 4048999, 2707198, 914737, 1264286,
-                1508332, 888040, 3620073, 3094649, 889244, 2738166, 1111887, 3381587, 2135222,
-                2591541, 1507552, 2266875, 1807565, 3783448, 3227488, 800092, 1110895, 547108,
-            ],
-        },
-        23 => Proof {
-            k,
-            plot_seed,
-            challenge: vec![
-                140, 68, 99, 54, 232, 127, 7, 76, 16, 142, 107, 249, 168, 126, 107, 139, 8, 163,
-                255, 57, 54, 221, 139, 159, 175, 120, 246, 228, 68, 141, 187, 12,
-            ],
-            x_values: vec![
-                6455406, 8097893, 2452812, 1898921, 3100449, 7945133, 5348532, 1919633, 1960876,
-                6702738, 5197967, 2702565, 3252791, 4205992, 2890007, 6417898, 7485372, 578228,
-                4779214, 2143205, 1820487, 6487608, 3183976, 4013145, 6326730, 6841373, 1794984,
-                3828663, 3184250, 2179181, 2630247, 7044644, 8119090, 538928, 7659977, 7580882,
-                7300833, 7631942, 4209684, 4885359, 4018345, 759714, 7461405, 1484874, 7889297,
-                2510903, 2535787, 7405421, 6117598, 7492140, 6296865, 3681233, 3706547, 7141159,
-                1492674, 4356366, 5841654, 7401142, 3995816, 2138375, 7253529, 4704463, 4308537,
-                672352,
-            ],
-        },
-        24 => Proof {
-            k,
-            plot_seed,
-            challenge: vec![
-                144, 123, 11, 221, 104, 85, 239, 89, 29, 145, 216, 197, 80, 113, 141, 53, 10, 17,
-                88, 158, 221, 19, 230, 239, 110, 219, 195, 31, 174, 78, 214, 191,
-            ],
-            x_values: vec![
-                16214919, 3604170, 3286943, 12072286, 6295911, 9298125, 11610882, 14152234,
-                7717550, 12865557, 8040681, 16603749, 15839384, 14263502, 11870180, 1250164,
-                12193961, 14526238, 14476708, 13420636, 956816, 4891839, 9991983, 9675947,
-                13805569, 6514213, 3204948, 3107130, 11872173, 3536497, 10650436, 7813634, 9557272,
-                12634849, 1357113, 1908689, 1895476, 7953062, 4473611, 8893278, 4656652, 8164283,
-                7280613, 155232, 3635812, 14465446, 3654498, 7124666, 13213784, 16001490, 4747268,
-                14534318, 5439723, 10101096, 1117101, 10941456, 745298, 14275134, 13996681,
-                14980356, 13244641, 5194908, 4844487, 5399705,
-            ],
-        },
-        25 => Proof {
-            k,
-            plot_seed,
-            challenge: vec![
-                144, 152, 10, 181, 73, 130, 35, 247, 217, 187, 27, 183, 205, 119, 253, 176, 144,
-                123, 122, 44, 23, 12, 87, 203, 241, 128, 6, 210, 32, 84, 254, 181,
-            ],
-            x_values: vec![
-                15572556, 20559682, 29906792, 1120869, 9552541, 14929477, 30516153, 4575415,
-                9228232, 21275747, 18797224, 27675734, 28043894, 28369314, 19324126, 20870736,
-                12060547, 2234124, 20330081, 23806291, 22991617, 3544089, 2102017, 1485451,
-                7611391, 30902337, 18469245, 23202534, 30085282, 13496416, 14706732, 22560214,
-                8540692, 3728930, 10003405, 16637866, 12332892, 7594630, 7526939, 13376368,
-                8629751, 18290949, 10876109, 30008281, 27787132, 15317838, 17958357, 23782375,
-                22085780, 28167331, 4286036, 4734404, 3789708, 6328815, 23542672, 19168715,
-                2205712, 33344495, 27169606, 17169536, 31617452, 3321761, 24597399, 10610386,
-            ],
-        },
-        _ => Proof {
-            k,
-            x_values: Vec::new(),
-            challenge: Vec::new(),
-            plot_seed: [0; 32],
-        },
+/// Prover of the benchmarks, which answer the challenge whether it passes the
+/// plot filter or not
+fn benchmark_prover(pos: PoSpace, challenge: &[u8]) -> Prover {
+    let prover = Prover::new(pos);
+    info!(
+        "Challenge {} {} the plot filter",
+        hex::encode(challenge),
+        if prover.passes_plot_filter(challenge, &[]) {
+            "passes"
+        } else {
+            "does not pass"
+        }
+    );
+    prover.with_plot_filter(0)
+}
+
+/// File storing the plotting queue, in the working directory
+const PLOT_QUEUE_FILE: &str = "plot_queue";
+
+/// Reads the farmer public key of the first stored keypair, a new keypair is
+/// generated if none exists
+fn default_farmer_key(keypairs_path: &Path) -> Result<Ed25519PublicKey> {
+    create_dir_all(keypairs_path)?;
+    let keypairs = read_all_keypair(keypairs_path).context("Could not read the keypairs")?;
+    match keypairs.first() {
+        Some(keypair) => Ok(keypair.public),
+        None => {
+            let keypair = Ed25519KeyPair::generate();
+            store_keypair(&keypair, keypairs_path)?;
+            info!("New farmer keypair generated and stored");
+            Ok(keypair.public)
+        }
+    }
+}
+
+fn parse_public_key(key: &str) -> Result<Ed25519PublicKey> {
+    let bytes = hex::decode(key).context("The public key must be hexadecimal")?;
+    Ed25519PublicKey::try_from_slice(&bytes).context("Invalid public key")
+}
+
+/// New plot id with a random nonce for the keys given as options
+fn plot_id_from_options(farmer_key: Option<&str>, pool_key: Option<&str>) -> Result<PlotId> {
+    let farmer_key = match farmer_key {
+        Some(key) => parse_public_key(key).context("Invalid farmer key")?,
+        None => default_farmer_key(Path::new("keypair_data"))?,
+    };
+    let pool_key = match pool_key {
+        Some(key) => parse_public_key(key).context("Invalid pool key")?,
+        None => farmer_key,
+    };
+    let mut nonce = [0u8; 32];
+    OsRng.fill_bytes(&mut nonce);
+    Ok(PlotId::new(farmer_key, pool_key, nonce))
+}
+
+/// Prints a progress bar each time the plotting progresses by one percent
+struct ProgressBar {
+    term: Term,
+    last_percent: AtomicU64,
+}
+
+impl ProgressBar {
+    const WIDTH: usize = 40;
+
+    fn new() -> Self {
+        ProgressBar {
+            term: Term::stderr(),
+            last_percent: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ProgressSink for ProgressBar {
+    fn progress(&self, phase: usize, percentage: f64, bytes_written: u64) {
+        let percent = percentage as u64;
+        if self.last_percent.swap(percent, Ordering::Relaxed) == percent {
+            return;
+        }
+        let filled = Self::WIDTH * percent as usize / 100;
+        self.term
+            .write_line(&format!(
+                "[{}{}] {:>3}% | phase {} | {} MiB written",
+                style("#".repeat(filled)).cyan(),
+                "-".repeat(Self::WIDTH - filled),
+                percent,
+                phase,
+                bytes_written >> 20
+            ))
+            .ok();
+    }
+}
+
+/// Plot files found in the directory, sorted by name
+fn list_plots(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut plots = read_dir(path)
+        .context(format!("Could not read the directory {:?}", path))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == PLOT_EXTENSION))
+        .collect::<Vec<PathBuf>>();
+    plots.sort();
+    Ok(plots)
+}
+
+/// Opens the first plot of size k found in the directory
+fn open_plot(k: usize, path: &Path) -> Result<PoSpace> {
+    let prefix = format!("plot-k{}-", k);
+    let plot_path = list_plots(path)?
+        .into_iter()
+        .find(|p| {
+            p.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .ok_or_else(|| anyhow!("No plot with k={} found in {:?}", k, path))?;
+    PoSpace::open(&plot_path).context("Failed to open the plot")
+}
+
+/// Checks every plot of the path, which is either a plot or a directory of plots
+fn check_plots(path: &Path, challenges: usize) -> Result<()> {
+    let plots = if path.is_dir() {
+        list_plots(path)?
+    } else {
+        vec![path.to_owned()]
+    };
+    if plots.is_empty() {
+        return Err(anyhow!("No plot found in {:?}", path));
+    }
+
+    let mut unhealthy = 0;
+    for plot_path in &plots {
+        info!("Checking plot {:?}", plot_path);
+        let report = match PoSpace::open(plot_path).and_then(|pos| pos.check(challenges)) {
+            Ok(report) => report,
+            Err(e) => {
+                error!("Plot {:?} is invalid: {:#}", plot_path, e);
+                unhealthy += 1;
+                continue;
+            }
+        };
+        for (i, entries) in report.table_entries.iter().enumerate() {
+            info!("Table {}: {} entries", i + 1, entries);
+        }
+        if report.is_healthy() {
+            info!("Plot {:?} is healthy", plot_path);
+        } else {
+            error!(
+                "Plot {:?} is corrupted: {} invalid proofs, {} challenges failed, {:.3} proofs per challenge ({:.3} expected)",
+                plot_path,
+                report.invalid_proofs,
+                report.failed_challenges,
+                report.proofs_per_challenge(),
+                report.expected_proofs_per_challenge()
+            );
+            unhealthy += 1;
+        }
+    }
+
+    if unhealthy > 0 {
+        return Err(anyhow!(
+            "{} of {} plots are invalid",
+            unhealthy,
+            plots.len()
+        ));
+    }
+    Ok(())
+}
+
+fn run_queue_command(cmd: QueueCommand) -> Result<()> {
+    let queue_path = Path::new(PLOT_QUEUE_FILE);
+    match cmd {
+        QueueCommand::Add {
+            space,
+            tmp_dir,
+            final_dir,
+            memory,
+            farmer_key,
+            pool_key,
+            count,
+        } => {
+            let _lock = QueueLock::acquire(queue_path)?;
+            let mut queue = PlotQueue::load(queue_path)?;
+            for _ in 0..count {
+                let plot_id = plot_id_from_options(farmer_key.as_deref(), pool_key.as_deref())?;
+                let id = queue
+                    .add(space, plot_id, &tmp_dir, &final_dir, memory * 1024 * 1024)
+                    .context("Invalid plot job")?;
+                info!("Job {} added (k = {})", id, space);
+            }
+            queue.save(queue_path)
+        }
+        QueueCommand::List => {
+            let queue = PlotQueue::load(queue_path)?;
+            if queue.jobs.is_empty() {
+                info!("The queue is empty");
+            }
+            for job in &queue.jobs {
+                info!(
+                    "Job {} | k = {} | {:>5.1}% | {} -> {} | {}",
+                    job.id, job.k, job.progress, job.tmp_dir, job.final_dir, job.status
+                );
+            }
+            Ok(())
+        }
+        QueueCommand::Cancel { id } => {
+            let _lock = QueueLock::acquire(queue_path)?;
+            let mut queue = PlotQueue::load(queue_path)?;
+            queue.cancel(id)?;
+            queue.save(queue_path)?;
+            info!("Job {} cancelled", id);
+            Ok(())
+        }
+        QueueCommand::Run {
+            parallel,
+            stagger,
+            threads,
+        } => {
+            let mut manager = PlotManager::new(queue_path, parallel, Duration::from_secs(stagger))?;
+            if let Some(threads) = threads {
+                manager = manager.with_threads(threads);
+            }
+            manager.run()
+        }
     }
 }
 
@@ -267,25 +482,43 @@ fn main() -> Result<()> {
     .unwrap();
 
     match opt.cmd {
-        Command::Init { space } => {
-            let mut plot_seed = [0u8; 32];
-            OsRng.fill_bytes(&mut plot_seed);
-            info!("Plot seed generated");
-            let pos = PoSpace::new(space, *b"aaaabbbbccccddddaaaabbbbccccdddd", "data".as_ref())
-                .context("Failed to create proof of space instance")?;
-            pos.run_phase_1()
-                .context("Failed to run phase 1 of plotting")
+        Command::Init {
+            space,
+            tmp_dir,
+            tmp2_dir,
+            final_dir,
+            memory,
+            threads,
+            buckets,
+            farmer_key,
+            pool_key,
+        } => {
+            let config = PlottingConfig::new(
+                memory * 1024 * 1024,
+                threads.unwrap_or_else(|| PlottingConfig::default().threads()),
+                buckets,
+            )
+            .context("Invalid plotting options")?;
+            let plot_id = plot_id_from_options(farmer_key.as_deref(), pool_key.as_deref())?;
+            info!("Plot id generated");
+            let pos = PoSpace::new(space, plot_id, &tmp_dir)
+                .context("Failed to create proof of space instance")?
+                .with_directories(&tmp_dir, tmp2_dir.as_deref(), &final_dir)
+                .with_config(config)
+                .with_progress(Arc::new(ProgressBar::new()));
+            pos.plot().context("Failed to create the plot")
         }
-        Command::Prove { space } => {
-            let challenge = get_challenge(space);
-            // OsRng.fill_bytes(&mut challenge);
-            let pos = PoSpace::new(space, *b"aaaabbbbccccddddaaaabbbbccccdddd", "data".as_ref())
-                .context("Failed to create proof of space instance")?;
-            let prover = Prover::new(pos);
-            // prover.get_quality_string(challenge.as_ref());
-            prover
-                .retrieve_all_proofs(challenge.as_ref())
+        Command::Prove { space, challenge } => {
+            let challenge = parse_challenge(challenge.as_deref())?;
+            let prover = benchmark_prover(open_plot(space, Path::new("data"))?, &challenge);
+            let qualities = prover
+                .get_quality_string(&challenge, &[])
+                .context("Cannot retrieve quality strings for challenge")?;
+            info!("{} quality strings found", qualities.len());
+            let proofs = prover
+                .retrieve_all_proofs(&challenge, &[])
                 .context("Cannot retrieve all proofs for challange")?;
+            info!("{} proofs found", proofs.len());
 
             Ok(())
         }
@@ -392,13 +625,59 @@ fn main() -> Result<()> {
 
             Ok(())
         }
-        Command::Verify { space } => {
+        Command::Verify { space, challenge } => {
+            let challenge = parse_challenge(challenge.as_deref())?;
+            let prover = benchmark_prover(open_plot(space, Path::new("data"))?, &challenge);
+            let proofs = prover
+                .retrieve_all_proofs(&challenge, &[])
+                .context("Cannot retrieve all proofs for challange")?;
+            let verifier = Verifier::new().with_plot_filter(0);
+            for result in verifier.verify_batch(&proofs) {
+                result.context("Could not verify the proof")?;
+            }
+            info!("{} proofs verified", proofs.len());
+            Ok(())
+        }
+        Command::Harvest {
+            plot_dirs,
+            disk_concurrency,
+            interval,
+        } => {
+            let harvester = Harvester::new(&plot_dirs).with_disk_concurrency(disk_concurrency);
             let verifier = Verifier::new();
-            let proof = get_proof(space);
-            verifier
-                .verify_proof(&proof)
-                .context("Could not verify the proof")
+            loop {
+                harvester.refresh()?;
+                let mut challenge = [0u8; 32];
+                let mut signage_data = [0u8; 32];
+                OsRng.fill_bytes(&mut challenge);
+                OsRng.fill_bytes(&mut signage_data);
+                let qualities = harvester.get_qualities(&challenge, &signage_data);
+                info!(
+                    "{} qualities found in {} plots",
+                    qualities.len(),
+                    harvester.plots().len()
+                );
+
+                // Only the proof of the best quality is retrieved
+                if let Some(best) = qualities.iter().min_by(|a, b| a.quality.cmp(&b.quality)) {
+                    let proof = harvester.get_proof(
+                        &best.plot_path,
+                        &challenge,
+                        &signage_data,
+                        best.index,
+                    )?;
+                    verifier
+                        .verify_proof(&proof)
+                        .context("Could not verify the proof")?;
+                    info!("Proof found in {:?}", best.plot_path);
+                }
+                sleep(Duration::from_secs(interval));
+            }
         }
+        Command::Queue { cmd } => run_queue_command(cmd),
+        Command::Plots { cmd } => match cmd {
+            PlotsCommand::Check { path, challenges } => check_plots(&path, challenges),
+        },
         Command::Demo { k } => {
             const INITIAL_KEYPAIRS: usize = 3;
             const INITIAL_AMOUNT: u64 = 100;
@@ -406,8 +685,9 @@ fn main() -> Result<()> {
             let chain_path = Path::new("blockchain_data");
             let keypairs_path = Path::new("keypair_data");
 
-            let pospace = PoSpace::new(k, *b"aaaabbbbccccddddaaaabbbbccccdddd", "data".as_ref())?;
-            let prover = Prover::new(pospace);
+            let pospace = open_plot(k, Path::new("data"))?;
+            // A single plot is farmed, with the plot filter a block would take hours to be proved
+            let prover = Prover::new(pospace).with_plot_filter(0);
 
             let mut keypairs = match read_all_keypair(keypairs_path) {
                 Ok(keypairs) => keypairs,
@@ -570,12 +850,16 @@ fn main() -> Result<()> {
                     }
                     3 => {
                         println!("");
-                        let verifier = Verifier::new();
+                        let verifier = Verifier::new().with_plot_filter(0);
+                        let proofs = ledger
+                            .blockchain
+                            .iter()
+                            .filter_map(|block| block.proof.clone())
+                            .collect::<Vec<Proof>>();
+                        let mut results = verifier.verify_batch(&proofs).into_iter();
                         for block in &ledger.blockchain {
-                            let is_proof_valid = block
-                                .proof
-                                .as_ref()
-                                .map_or(false, |p| verifier.verify_proof(&p).is_ok());
+                            let is_proof_valid =
+                                block.proof.is_some() && matches!(results.next(), Some(Ok(())));
                             println!("Height: {}", block.height);
                             println!(
                                 "Hash: {}",
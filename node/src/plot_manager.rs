@@ -0,0 +1,429 @@
+use anyhow::{anyhow, Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use log::*;
+use spaceframe_pospace::config::{PlottingConfig, DEFAULT_BUCKETS};
+use spaceframe_pospace::constants::{MAX_K, MIN_K};
+use spaceframe_pospace::core::PoSpace;
+use spaceframe_pospace::error::PoSpaceError;
+use spaceframe_pospace::plot_id::PlotId;
+use spaceframe_pospace::progress::{CancellationToken, ProgressSink};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{remove_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Time between two updates of the queue file by the manager
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// A lock older than this is left by a process which has been killed
+const STALE_LOCK_AGE: Duration = Duration::from_secs(10);
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// The plot has been written at the given path
+    Done(String),
+    Failed(String),
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Queued => write!(f, "queued"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Done(path) => write!(f, "done: {}", path),
+            JobStatus::Failed(error) => write!(f, "failed: {}", error),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Plot to create, the plot id is chosen when the job is added so that an
+/// interrupted job resumes from its checkpoint
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct PlotJob {
+    pub id: u64,
+    pub k: u8,
+    pub plot_id: PlotId,
+    pub tmp_dir: String,
+    pub final_dir: String,
+    /// Memory budget in bytes
+    pub memory_budget: u64,
+    pub status: JobStatus,
+    /// Progress of the plotting in percent
+    pub progress: f32,
+}
+
+/// Jobs of the plotting queue, stored in a single file
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq)]
+pub struct PlotQueue {
+    pub next_id: u64,
+    pub jobs: Vec<PlotJob>,
+}
+
+impl PlotQueue {
+    /// An empty queue is returned if the file does not exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut buffer = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => file
+                .read_to_end(&mut buffer)
+                .context(format!("Could not read the queue {:?}", path))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(PlotQueue::default()),
+            Err(e) => return Err(e).context(format!("Could not open the queue {:?}", path)),
+        };
+        PlotQueue::try_from_slice(&buffer).context(format!("Invalid queue file {:?}", path))
+    }
+
+    /// Writes the queue, the previous file is only replaced once the new one is complete
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        File::create(&tmp_path)
+            .context(format!("Could not create {:?}", tmp_path))?
+            .write_all(&self.try_to_vec()?)
+            .context(format!("Could not write {:?}", tmp_path))?;
+        rename(&tmp_path, path).context(format!("Could not replace the queue {:?}", path))
+    }
+
+    /// Queues a plot job, k is checked like when a plot is created directly
+    pub fn add(
+        &mut self,
+        k: usize,
+        plot_id: PlotId,
+        tmp_dir: &Path,
+        final_dir: &Path,
+        memory_budget: usize,
+    ) -> Result<u64> {
+        if !(MIN_K..=MAX_K).contains(&k) {
+            return Err(PoSpaceError::InvalidK(k).into());
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.push(PlotJob {
+            id,
+            k: k as u8,
+            plot_id,
+            tmp_dir: tmp_dir.to_string_lossy().into_owned(),
+            final_dir: final_dir.to_string_lossy().into_owned(),
+            memory_budget: memory_budget as u64,
+            status: JobStatus::Queued,
+            progress: 0.0,
+        });
+        Ok(id)
+    }
+
+    pub fn job_mut(&mut self, id: u64) -> Option<&mut PlotJob> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    /// A queued job is cancelled at once, a running one when the manager sees it
+    pub fn cancel(&mut self, id: u64) -> Result<()> {
+        let job = self
+            .job_mut(id)
+            .ok_or_else(|| anyhow!("No job with id {}", id))?;
+        if job.status.is_finished() {
+            return Err(anyhow!("Job {} is already finished", id));
+        }
+        job.status = JobStatus::Cancelled;
+        Ok(())
+    }
+}
+
+/// Exclusive access to the queue file between the processes of the node
+pub struct QueueLock {
+    path: PathBuf,
+}
+
+impl QueueLock {
+    pub fn acquire(queue_path: &Path) -> Result<Self> {
+        let path = queue_path.with_extension("lock");
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(QueueLock { path }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let age = path
+                        .metadata()
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+                    if age.is_some_and(|age| age > STALE_LOCK_AGE) {
+                        warn!("Removing the stale lock {:?}", path);
+                        remove_file(&path).ok();
+                    }
+                    sleep(Duration::from_millis(20));
+                }
+                Err(e) => return Err(e).context(format!("Could not create the lock {:?}", path)),
+            }
+        }
+    }
+}
+
+impl Drop for QueueLock {
+    fn drop(&mut self) {
+        remove_file(&self.path).ok();
+    }
+}
+
+/// Runs the jobs of the queue, several at a time with a delay between two starts
+///
+/// The queue file is read again at every poll, jobs can be added or cancelled
+/// by other processes while the manager is running. Only one manager must run
+/// for a queue.
+pub struct PlotManager {
+    queue_path: PathBuf,
+    queue: Arc<Mutex<PlotQueue>>,
+    tokens: Mutex<HashMap<u64, CancellationToken>>,
+    parallel: usize,
+    stagger: Duration,
+    threads: usize,
+}
+
+impl PlotManager {
+    /// The jobs still running when the previous manager stopped are queued again
+    pub fn new(queue_path: &Path, parallel: usize, stagger: Duration) -> Result<Self> {
+        let mut queue = {
+            let _lock = QueueLock::acquire(queue_path)?;
+            PlotQueue::load(queue_path)?
+        };
+        for job in &mut queue.jobs {
+            if job.status == JobStatus::Running {
+                info!("Job {} was interrupted, it will be resumed", job.id);
+                job.status = JobStatus::Queued;
+            }
+        }
+        let parallel = parallel.max(1);
+        Ok(PlotManager {
+            queue_path: queue_path.to_owned(),
+            queue: Arc::new(Mutex::new(queue)),
+            tokens: Mutex::new(HashMap::new()),
+            parallel,
+            stagger,
+            threads: (PlottingConfig::default().threads() / parallel).max(1),
+        })
+    }
+
+    /// Number of threads of each job, defaults to the CPUs shared between the
+    /// parallel jobs
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Runs until every job of the queue is finished
+    pub fn run(&self) -> Result<()> {
+        thread::scope(|s| -> Result<()> {
+            let mut last_start: Option<Instant> = None;
+            loop {
+                self.sync()?;
+                let (running, next_job) = {
+                    let queue = self.queue.lock().unwrap();
+                    (
+                        queue
+                            .jobs
+                            .iter()
+                            .filter(|job| job.status == JobStatus::Running)
+                            .count(),
+                        queue
+                            .jobs
+                            .iter()
+                            .find(|job| job.status == JobStatus::Queued)
+                            .cloned(),
+                    )
+                };
+
+                match next_job {
+                    None if running == 0 => break,
+                    Some(job)
+                        if running < self.parallel
+                            && last_start.is_none_or(|start| start.elapsed() >= self.stagger) =>
+                    {
+                        let token = CancellationToken::new();
+                        self.tokens.lock().unwrap().insert(job.id, token.clone());
+                        self.set_status(job.id, JobStatus::Running);
+                        last_start = Some(Instant::now());
+                        s.spawn(move || self.run_job(job, token));
+                    }
+                    _ => sleep(POLL_INTERVAL),
+                }
+            }
+            Ok(())
+        })?;
+        self.sync()
+    }
+
+    fn run_job(&self, job: PlotJob, token: CancellationToken) {
+        info!("Starting job {} (k = {})", job.id, job.k);
+        let status = match self.plot(&job, token) {
+            Ok(plot_path) => {
+                info!("Job {} done", job.id);
+                JobStatus::Done(plot_path.to_string_lossy().into_owned())
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(PoSpaceError::Cancelled)) => {
+                info!("Job {} cancelled", job.id);
+                JobStatus::Cancelled
+            }
+            Err(e) => {
+                error!("Job {} failed: {:#}", job.id, e);
+                JobStatus::Failed(format!("{:#}", e))
+            }
+        };
+        self.tokens.lock().unwrap().remove(&job.id);
+        self.set_status(job.id, status);
+    }
+
+    fn plot(&self, job: &PlotJob, token: CancellationToken) -> Result<PathBuf> {
+        let config =
+            PlottingConfig::new(job.memory_budget as usize, self.threads, DEFAULT_BUCKETS)?;
+        // The names of the temporary tables are the same for every plot, each
+        // job needs its own directory
+        let tmp_dir = Path::new(&job.tmp_dir).join(format!("job-{}", job.id));
+        let pos = PoSpace::new(job.k as usize, job.plot_id, &tmp_dir)?
+            .with_directories(&tmp_dir, None, Path::new(&job.final_dir))
+            .with_config(config)
+            .with_progress(Arc::new(JobProgress {
+                id: job.id,
+                queue: self.queue.clone(),
+            }))
+            .with_cancellation(token);
+        pos.plot()?;
+        remove_dir_all(&tmp_dir).ok();
+        Ok(pos.plot_path().to_owned())
+    }
+
+    fn set_status(&self, id: u64, status: JobStatus) {
+        if let Some(job) = self.queue.lock().unwrap().job_mut(id) {
+            job.status = status;
+        }
+    }
+
+    /// Merges the jobs added or cancelled in the queue file, then saves the
+    /// state of the jobs run by the manager
+    fn sync(&self) -> Result<()> {
+        let _lock = QueueLock::acquire(&self.queue_path)?;
+        let on_disk = PlotQueue::load(&self.queue_path)?;
+        let mut queue = self.queue.lock().unwrap();
+        queue.next_id = queue.next_id.max(on_disk.next_id);
+
+        for job in on_disk.jobs {
+            match queue.job_mut(job.id) {
+                None => queue.jobs.push(job),
+                Some(local) if job.status == JobStatus::Cancelled => {
+                    if local.status == JobStatus::Running {
+                        if let Some(token) = self.tokens.lock().unwrap().get(&job.id) {
+                            token.cancel();
+                        }
+                    } else if local.status == JobStatus::Queued {
+                        local.status = JobStatus::Cancelled;
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        queue.save(&self.queue_path)
+    }
+}
+
+/// Keeps the progress of a job up to date in the queue
+struct JobProgress {
+    id: u64,
+    queue: Arc<Mutex<PlotQueue>>,
+}
+
+impl ProgressSink for JobProgress {
+    fn progress(&self, _phase: usize, percentage: f64, _bytes_written: u64) {
+        if let Some(job) = self.queue.lock().unwrap().job_mut(self.id) {
+            job.progress = percentage as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+    use spaceframe_crypto::traits::PrivateKey;
+    use tempdir::TempDir;
+
+    fn test_plot_id() -> PlotId {
+        let public_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+            .unwrap()
+            .public_key();
+        PlotId::new(public_key, public_key, [0; 32])
+    }
+
+    #[test]
+    fn test_queue_persistence() {
+        let dir = TempDir::new("spaceframe_queue").unwrap();
+        let path = dir.path().join("plot_queue");
+        assert_eq!(PlotQueue::load(&path).unwrap(), PlotQueue::default());
+
+        let mut queue = PlotQueue::default();
+        let first = queue.add(14, test_plot_id(), dir.path(), dir.path(), 1 << 20);
+        let second = queue.add(15, test_plot_id(), dir.path(), dir.path(), 1 << 20);
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_ne!(first, second);
+        for k in [MIN_K - 1, MAX_K + 1, 300] {
+            let err = queue
+                .add(k, test_plot_id(), dir.path(), dir.path(), 1 << 20)
+                .unwrap_err();
+            assert!(
+                matches!(err.downcast_ref(), Some(PoSpaceError::InvalidK(found)) if *found == k)
+            );
+        }
+        assert_eq!(queue.jobs.len(), 2);
+        queue.cancel(second).unwrap();
+        assert!(queue.cancel(second).is_err());
+        queue.save(&path).unwrap();
+
+        let loaded = PlotQueue::load(&path).unwrap();
+        assert_eq!(loaded, queue);
+        assert_eq!(loaded.jobs[0].status, JobStatus::Queued);
+        assert_eq!(loaded.jobs[1].status, JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_run_queue() {
+        let dir = TempDir::new("spaceframe_queue").unwrap();
+        let path = dir.path().join("plot_queue");
+        let mut queue = PlotQueue::default();
+        let done = queue
+            .add(
+                12,
+                test_plot_id(),
+                dir.path(),
+                &dir.path().join("plots"),
+                1 << 20,
+            )
+            .unwrap();
+        let cancelled = queue
+            .add(12, test_plot_id(), dir.path(), dir.path(), 1 << 20)
+            .unwrap();
+        queue.cancel(cancelled).unwrap();
+        queue.save(&path).unwrap();
+
+        PlotManager::new(&path, 2, Duration::from_secs(0))
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let queue = PlotQueue::load(&path).unwrap();
+        assert!(matches!(
+            queue.jobs[done as usize].status,
+            JobStatus::Done(_)
+        ));
+        assert_eq!(queue.jobs[done as usize].progress, 100.0);
+        assert_eq!(queue.jobs[cancelled as usize].status, JobStatus::Cancelled);
+        assert!(!path.with_extension("lock").exists());
+    }
+}
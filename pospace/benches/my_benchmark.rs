@@ -15,6 +15,11 @@ fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| fx.calculate_f1(black_box(&BitsWrapper::from(0xabcd, 12))))
     });
 
+    calculate_fn_group.bench_function("calculate_f1_range", |b| {
+        let fx = F1Calculator::new(12, *b"aaaabbbbccccddddaaaabbbbccccdddd");
+        b.iter(|| fx.calculate_f1_range(black_box(0xabcd), black_box(4096)))
+    });
+
     calculate_fn_group.bench_function("calculate_fn_table2", |b| {
         let fx = FxCalculator::new(12, 2);
         let y1 = to_bits(0xabcbef, 12 + PARAM_EXT);
@@ -38,6 +43,15 @@ fn criterion_benchmark(c: &mut Criterion) {
         let right = to_bits(0xefab, 12);
         b.iter(|| fx.calculate_fn(black_box(&y1), black_box(&left), black_box(&right)))
     });
+
+    for table_index in 2..=4 {
+        calculate_fn_group.bench_function(format!("calculate_fn_int_table{}", table_index), |b| {
+            let fx = FxCalculator::new(12, table_index);
+            b.iter(|| {
+                fx.calculate_fn_int(black_box(0xabcbef), black_box(0xabcd), black_box(0xefab))
+            })
+        });
+    }
     calculate_fn_group.finish();
 
     let mut bits_group = c.benchmark_group("bits");
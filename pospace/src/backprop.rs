@@ -0,0 +1,219 @@
+use crate::bitfield::Bitfield;
+use crate::config::PlottingConfig;
+use crate::error::{PoSpaceError, SortError, StorageError};
+use crate::storage::{serialize, ChunkReader, PlotEntry};
+use crate::{table_final_filename_format, table_tmp_filename_format};
+use anyhow::{Context, Result};
+use log::*;
+use std::fs::{rename, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Removes the entries of table `table_index - 1` that are not referenced by
+/// table `table_index` and updates the positions of table `table_index`
+///
+/// Table `table_index` must already be pruned, except for table 7 which is
+/// never pruned.
+pub fn backpropagate_table(
+    table_index: usize,
+    path: &Path,
+    k: usize,
+    config: &PlottingConfig,
+) -> Result<()> {
+    assert!(table_index >= 2);
+
+    let previous_count = ChunkReader::new(path, table_index - 1, k, config)?.entries_count;
+    let config = &config.backprop_config(previous_count)?;
+    let mut used_entries = mark_used_entries(table_index, path, k, previous_count, config)
+        .context(format!(
+            "Could not mark used entries of table {}",
+            table_index - 1
+        ))?;
+    used_entries.build_index();
+
+    info!(
+        "[Table {}] {} entries of {} are used by table {}",
+        table_index - 1,
+        used_entries.count_ones(),
+        used_entries.len(),
+        table_index
+    );
+
+    rewrite_table(table_index, path, k, config, |entry| {
+        let position = entry.position.ok_or(PoSpaceError::EmptyPosition)?;
+        let offset = entry.offset.ok_or(PoSpaceError::EmptyOffset)?;
+        let new_position = used_entries.rank(position as usize);
+        let new_right_position = used_entries.rank((position + offset) as usize);
+        entry.position = Some(new_position);
+        entry.offset = Some(new_right_position - new_position);
+        Ok(true)
+    })
+    .context(format!(
+        "Could not update positions of table {}",
+        table_index
+    ))?;
+
+    let mut index = 0;
+    rewrite_table(table_index - 1, path, k, config, |_| {
+        let is_used = used_entries.get(index);
+        index += 1;
+        Ok(is_used)
+    })
+    .context(format!(
+        "Could not remove unused entries of table {}",
+        table_index - 1
+    ))?;
+
+    Ok(())
+}
+
+fn mark_used_entries(
+    table_index: usize,
+    path: &Path,
+    k: usize,
+    previous_count: usize,
+    config: &PlottingConfig,
+) -> Result<Bitfield> {
+    let mut used_entries = Bitfield::new(previous_count);
+    let mut chunk_reader = ChunkReader::new(path, table_index, k, config)?;
+
+    loop {
+        match chunk_reader.read_chunk() {
+            Ok(entries) => {
+                for entry in entries {
+                    let position = entry.position.ok_or(PoSpaceError::EmptyPosition)?;
+                    let offset = entry.offset.ok_or(PoSpaceError::EmptyOffset)?;
+                    if (position + offset) as usize >= previous_count {
+                        return Err(StorageError::InvalidFileContent.into());
+                    }
+                    used_entries.set(position as usize);
+                    used_entries.set((position + offset) as usize);
+                }
+            }
+            Err(e) => match e.downcast_ref::<StorageError>() {
+                Some(StorageError::EndOfFile) => break,
+                _ => return Err(e),
+            },
+        }
+    }
+
+    Ok(used_entries)
+}
+
+/// Rewrites a table by applying `f` to every entry, only the entries for
+/// which `f` returns `true` are kept
+fn rewrite_table<F>(
+    table_index: usize,
+    path: &Path,
+    k: usize,
+    config: &PlottingConfig,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(&mut PlotEntry) -> Result<bool>,
+{
+    let tmp_path = path.join(format!(table_tmp_filename_format!(), table_index));
+    let mut chunk_reader = ChunkReader::new(path, table_index, k, config)?;
+    let mut output = BufWriter::new(
+        File::create(&tmp_path).context(format!("Could not create file {:?}", tmp_path))?,
+    );
+
+    loop {
+        match chunk_reader.read_chunk() {
+            Ok(entries) => {
+                let mut kept_entries = Vec::with_capacity(entries.len());
+                for mut entry in entries {
+                    if f(&mut entry)? {
+                        kept_entries.push(entry);
+                    }
+                }
+                output
+                    .write_all(&serialize(&kept_entries, table_index, k)?)
+                    .context(format!("Could not write table {} to disk", table_index))?;
+            }
+            Err(e) => match e.downcast_ref::<StorageError>() {
+                Some(StorageError::EndOfFile) => break,
+                _ => return Err(e),
+            },
+        }
+    }
+
+    output.flush()?;
+    drop(output);
+    drop(chunk_reader);
+
+    rename(
+        &tmp_path,
+        path.join(format!(table_final_filename_format!(), table_index)),
+    )
+    .map_err(|e| SortError::RenameError(e.kind()))
+    .context(format!(
+        "Could not rename plot file for table {}",
+        table_index
+    ))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::{deserialize, store_table_part};
+    use std::io::Read;
+    use tempdir::TempDir;
+
+    fn read_table(path: &Path, table_index: usize, k: usize) -> Vec<PlotEntry> {
+        let mut buffer = Vec::new();
+        File::open(path.join(format!(table_final_filename_format!(), table_index)))
+            .unwrap()
+            .read_to_end(&mut buffer)
+            .unwrap();
+        deserialize(&buffer, table_index, k).unwrap()
+    }
+
+    #[test]
+    fn test_backpropagate_table() {
+        const TEST_K: usize = 12;
+        let dir = TempDir::new("spaceframe_backprop").unwrap();
+
+        let table6 = (0..6)
+            .map(|x| PlotEntry {
+                fx: x,
+                metadata: Some(x as u128),
+                position: Some(0),
+                offset: Some(1),
+            })
+            .collect::<Vec<PlotEntry>>();
+        let table7 = vec![
+            PlotEntry {
+                fx: 10,
+                metadata: Some(0),
+                position: Some(1),
+                offset: Some(2),
+            },
+            PlotEntry {
+                fx: 11,
+                metadata: Some(0),
+                position: Some(3),
+                offset: Some(2),
+            },
+        ];
+        store_table_part(&table6, &dir.path().join("table6_final"), 6, TEST_K).unwrap();
+        store_table_part(&table7, &dir.path().join("table7_final"), 7, TEST_K).unwrap();
+
+        backpropagate_table(7, dir.path(), TEST_K, &PlottingConfig::default()).unwrap();
+
+        let pruned_table6 = read_table(dir.path(), 6, TEST_K);
+        assert_eq!(
+            pruned_table6.iter().map(|e| e.fx).collect::<Vec<u64>>(),
+            vec![1, 3, 5]
+        );
+
+        let updated_table7 = read_table(dir.path(), 7, TEST_K);
+        assert_eq!(updated_table7[0].position, Some(0));
+        assert_eq!(updated_table7[0].offset, Some(1));
+        assert_eq!(updated_table7[1].position, Some(1));
+        assert_eq!(updated_table7[1].offset, Some(1));
+    }
+}
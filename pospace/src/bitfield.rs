@@ -0,0 +1,106 @@
+use std::mem::size_of;
+
+/// Number of words sharing a rank, the ranks take an eighth of the size of the words
+const WORDS_PER_RANK: usize = 8;
+
+/// Fixed size set of bits able to tell how many bits are set before a given index
+#[derive(Debug, Clone)]
+pub struct Bitfield {
+    words: Vec<u64>,
+    ranks: Vec<u64>,
+    size: usize,
+}
+
+impl Bitfield {
+    pub fn new(size: usize) -> Self {
+        Bitfield {
+            words: vec![0; size.div_ceil(64)],
+            ranks: Vec::new(),
+            size,
+        }
+    }
+
+    /// Number of bytes used by a bitfield of `size` bits once its index is built
+    pub fn memory_size(size: usize) -> usize {
+        let words = size.div_ceil(64);
+        (words + words.div_ceil(WORDS_PER_RANK)) * size_of::<u64>()
+    }
+
+    pub fn set(&mut self, index: usize) {
+        assert!(index < self.size);
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn count_ones(&self) -> u64 {
+        self.words.iter().map(|w| w.count_ones() as u64).sum()
+    }
+
+    /// Must be called once all the bits are set and before calling `rank`
+    pub fn build_index(&mut self) {
+        self.ranks.clear();
+        let mut count = 0;
+        for block in self.words.chunks(WORDS_PER_RANK) {
+            self.ranks.push(count);
+            count += block.iter().map(|w| w.count_ones() as u64).sum::<u64>();
+        }
+    }
+
+    /// Number of bits set before the given index
+    pub fn rank(&self, index: usize) -> u64 {
+        assert!(index < self.size);
+        let word = index / 64;
+        let block_start = word - word % WORDS_PER_RANK;
+        let mask = (1u64 << (index % 64)) - 1;
+        self.ranks[word / WORDS_PER_RANK]
+            + self.words[block_start..word]
+                .iter()
+                .map(|w| w.count_ones() as u64)
+                .sum::<u64>()
+            + (self.words[word] & mask).count_ones() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_set_and_get() {
+        let mut bitfield = Bitfield::new(130);
+        bitfield.set(0);
+        bitfield.set(64);
+        bitfield.set(129);
+        assert!(bitfield.get(0));
+        assert!(!bitfield.get(1));
+        assert!(bitfield.get(64));
+        assert!(bitfield.get(129));
+        assert_eq!(bitfield.count_ones(), 3);
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut bitfield = Bitfield::new(2000);
+        for i in (0..2000).step_by(3) {
+            bitfield.set(i);
+        }
+        bitfield.build_index();
+        for i in 0..2000 {
+            assert_eq!(bitfield.rank(i), i.div_ceil(3) as u64);
+        }
+        assert_eq!(Bitfield::memory_size(2000), (32 + 4) * 8);
+    }
+}
@@ -51,6 +51,39 @@ pub fn from_bits(input: &BitsSlice) -> u64 {
     vec.load_le::<u64>()
 }
 
+/// Same as `from_bits` for up to 128 bits
+pub fn from_bits_u128(input: &BitsSlice) -> u128 {
+    input
+        .iter()
+        .fold(0, |value, bit| (value << 1) | *bit as u128)
+}
+
+/// Same as `to_bits` for up to 128 bits
+pub fn to_bits_u128(input: u128, size: usize) -> Bits {
+    (0..size).rev().map(|i| (input >> i) & 1 == 1).collect()
+}
+
+/// Value of the first `size` bits of a bit vector stored as raw bytes, like the
+/// metadata of the plot entries
+pub fn from_raw_bits(raw: &[u8], size: usize) -> u128 {
+    if size == 0 {
+        return 0;
+    }
+    let length = size.div_ceil(8);
+    let mut bytes = [0u8; 16];
+    bytes[..length].copy_from_slice(&raw[..length]);
+    u128::from_le_bytes(bytes).reverse_bits() >> (128 - size)
+}
+
+/// Raw bytes of the bit vector of `size` bits holding the value, the inverse
+/// of `from_raw_bits`
+pub fn to_raw_bits(value: u128, size: usize) -> Vec<u8> {
+    if size == 0 {
+        return Vec::new();
+    }
+    (value << (128 - size)).reverse_bits().to_le_bytes()[..size.div_ceil(8)].to_vec()
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -95,4 +128,26 @@ mod tests {
             bitvec![Lsb0, u8; 0, 0, 1, 0, 0, 0, 1]
         );
     }
+
+    #[test]
+    fn test_bits_u128() {
+        let value = 0x1234_5678_9abc_def0_1122_3344_5566u128;
+        assert_eq!(to_bits_u128(0xabcd, 16), to_bits(0xabcd, 16));
+        assert_eq!(from_bits_u128(&to_bits(0xabcd, 16)), 0xabcd);
+        assert_eq!(from_bits_u128(&to_bits_u128(value, 110)), value);
+    }
+
+    #[test]
+    fn test_raw_bits() {
+        let value = 0x1234_5678_9abc_def0_1122_3344_5566u128;
+        for size in [1, 12, 64, 110, 128] {
+            let value = value & (u128::MAX >> (128 - size));
+            let raw = to_raw_bits(value, size);
+            assert_eq!(raw, to_bits_u128(value, size).as_raw_slice());
+            assert_eq!(from_raw_bits(&raw, size), value);
+        }
+        // The bits after the size are ignored
+        assert_eq!(from_raw_bits(to_bits(0xabcd, 16).as_raw_slice(), 12), 0xabc);
+        assert!(to_raw_bits(value, 0).is_empty());
+    }
 }
@@ -0,0 +1,124 @@
+use crate::core::{PoSpace, NUMBER_OF_TABLES};
+use crate::plot::PlotFile;
+use crate::proofs::Prover;
+use crate::verifier::Verifier;
+use anyhow::{Context, Result};
+use log::*;
+use rand::{thread_rng, RngCore};
+
+/// Largest relative gap between the number of proofs found and the number
+/// expected from the size of table 7 for a healthy plot
+const PROOFS_TOLERANCE: f64 = 0.5;
+
+/// Result of the integrity check of a plot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlotCheckReport {
+    pub k: usize,
+    /// Number of entries in each table
+    pub table_entries: [u64; NUMBER_OF_TABLES],
+    pub challenges: usize,
+    /// Number of proofs found for all the challenges
+    pub proofs: usize,
+    /// Number of proofs rejected by the verifier
+    pub invalid_proofs: usize,
+    /// Number of challenges for which the proofs could not be read
+    pub failed_challenges: usize,
+}
+
+impl PlotCheckReport {
+    /// Average number of proofs found per challenge
+    pub fn proofs_per_challenge(&self) -> f64 {
+        if self.challenges == 0 {
+            return 0.0;
+        }
+        self.proofs as f64 / self.challenges as f64
+    }
+
+    /// Average number of proofs per challenge given by the size of table 7,
+    /// a challenge matches each entry with a probability of 1 / 2^k
+    pub fn expected_proofs_per_challenge(&self) -> f64 {
+        self.table_entries[NUMBER_OF_TABLES - 1] as f64 / (1u64 << self.k) as f64
+    }
+
+    /// Whether the number of proofs found is close to the expected one
+    ///
+    /// The number of proofs follows a Poisson distribution, the gap allowed is
+    /// wider when the challenges are too few for the tolerance to be meaningful.
+    pub fn has_expected_proofs(&self) -> bool {
+        let expected = self.expected_proofs_per_challenge() * self.challenges as f64;
+        let tolerance = (PROOFS_TOLERANCE * expected).max(4.0 * expected.sqrt());
+        (self.proofs as f64 - expected).abs() <= tolerance
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.invalid_proofs == 0 && self.failed_challenges == 0 && self.has_expected_proofs()
+    }
+}
+
+impl PoSpace {
+    /// Checks the layout of the plot file then issues `challenges` random
+    /// challenges passing the plot filter and verifies every proof found
+    ///
+    /// An invalid layout is returned as an error, the problems found while
+    /// proving are counted in the report.
+    pub fn check(&self, challenges: usize) -> Result<PlotCheckReport> {
+        let plot_file = PlotFile::open(self.plot_path())?;
+        plot_file
+            .validate_layout()
+            .context(format!("Invalid layout of plot {:?}", self.plot_path()))?;
+
+        let mut report = PlotCheckReport {
+            k: self.k,
+            table_entries: [0; NUMBER_OF_TABLES],
+            challenges,
+            proofs: 0,
+            invalid_proofs: 0,
+            failed_challenges: 0,
+        };
+        for (table_index, entries) in report.table_entries.iter_mut().enumerate() {
+            *entries = plot_file.header.entries_count(table_index + 1);
+        }
+
+        let prover = Prover::new(self.clone());
+        let verifier = Verifier::new();
+        let mut rng = thread_rng();
+
+        for _ in 0..challenges {
+            // Only the challenges that pass the plot filter are kept
+            let mut challenge = [0u8; 32];
+            let mut signage_data = [0u8; 32];
+            rng.fill_bytes(&mut challenge);
+            loop {
+                rng.fill_bytes(&mut signage_data);
+                if prover.passes_plot_filter(&challenge, &signage_data) {
+                    break;
+                }
+            }
+            let proofs = match prover.retrieve_all_proofs(&challenge, &signage_data) {
+                Ok(proofs) => proofs,
+                Err(e) => {
+                    warn!("Could not retrieve the proofs of a challenge: {:#}", e);
+                    report.failed_challenges += 1;
+                    continue;
+                }
+            };
+            report.proofs += proofs.len();
+            for proof in &proofs {
+                if let Err(e) = verifier.verify_proof(proof) {
+                    warn!("Invalid proof found: {:#}", e);
+                    report.invalid_proofs += 1;
+                }
+            }
+        }
+
+        info!(
+            "{} proofs found for {} challenges ({:.3} proofs per challenge, {:.3} expected)",
+            report.proofs,
+            challenges,
+            report.proofs_per_challenge(),
+            report.expected_proofs_per_challenge()
+        );
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,222 @@
+use crate::core::PlotSeed;
+use crate::error::StorageError;
+use crate::table_final_filename_format;
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{read_dir, remove_file, rename, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub const CHECKPOINT_FILENAME: &str = "checkpoint";
+
+/// State of a table once it has been computed and sorted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TableCheckpoint {
+    pub table_index: usize,
+    pub file_size: u64,
+    pub hash: [u8; 32],
+}
+
+/// Tables completed during phase 1, used to resume an interrupted plotting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointManifest {
+    pub k: usize,
+    pub plot_seed: PlotSeed,
+    pub tables: Vec<TableCheckpoint>,
+}
+
+impl CheckpointManifest {
+    pub fn new(k: usize, plot_seed: PlotSeed) -> Self {
+        CheckpointManifest {
+            k,
+            plot_seed,
+            tables: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut buffer = Vec::new();
+        File::open(path.join(CHECKPOINT_FILENAME))
+            .context("Could not open checkpoint manifest")?
+            .read_to_end(&mut buffer)
+            .context("Could not read checkpoint manifest")?;
+        Ok(bincode::deserialize(&buffer).or(Err(StorageError::DeserializationError))?)
+    }
+
+    /// Writes the manifest, the previous one is only replaced once the new one is complete
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.join(format!("{}_tmp", CHECKPOINT_FILENAME));
+        let bin_data = bincode::serialize(self).or(Err(StorageError::SerializationError))?;
+        let mut file = File::create(&tmp_path).context("Could not create checkpoint manifest")?;
+        file.write_all(&bin_data)
+            .context("Could not write checkpoint manifest")?;
+        file.sync_all()?;
+        drop(file);
+        rename(&tmp_path, path.join(CHECKPOINT_FILENAME))
+            .context("Could not replace checkpoint manifest")?;
+        Ok(())
+    }
+
+    pub fn remove(path: &Path) -> Result<()> {
+        let manifest_path = path.join(CHECKPOINT_FILENAME);
+        if manifest_path.exists() {
+            remove_file(&manifest_path).context("Could not remove checkpoint manifest")?;
+        }
+        Ok(())
+    }
+
+    /// Records the final file of a table which has just been sorted
+    pub fn add_table(&mut self, table_index: usize, path: &Path) -> Result<()> {
+        let table_path = path.join(format!(table_final_filename_format!(), table_index));
+        self.tables.retain(|t| t.table_index < table_index);
+        self.tables.push(TableCheckpoint {
+            table_index,
+            file_size: table_path.metadata()?.len(),
+            hash: hash_file(&table_path)?,
+        });
+        Ok(())
+    }
+
+    /// Checks the recorded tables in order and returns the first one that must be
+    /// computed again, the tables after it are removed from the manifest
+    pub fn first_incomplete_table(&mut self, path: &Path) -> Result<usize> {
+        let mut valid_tables = 0;
+
+        for (i, checkpoint) in self.tables.iter().enumerate() {
+            if checkpoint.table_index != i + 1 {
+                break;
+            }
+            let table_path = path.join(format!(
+                table_final_filename_format!(),
+                checkpoint.table_index
+            ));
+            let is_valid = match table_path.metadata() {
+                Ok(metadata) => {
+                    metadata.len() == checkpoint.file_size
+                        && hash_file(&table_path)? == checkpoint.hash
+                }
+                Err(_) => false,
+            };
+            if !is_valid {
+                warn!(
+                    "[Table {}] Checkpoint does not match the table on disk",
+                    checkpoint.table_index
+                );
+                break;
+            }
+            valid_tables += 1;
+        }
+
+        self.tables.truncate(valid_tables);
+        Ok(valid_tables + 1)
+    }
+}
+
+pub fn hash_file(path: &Path) -> Result<[u8; 32]> {
+    let mut file = File::open(path).context(format!("Could not open {:?}", path))?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let amount = file
+            .read(&mut buffer)
+            .context(format!("Could not read {:?}", path))?;
+        if amount == 0 {
+            break;
+        }
+        hasher.update(&buffer[..amount]);
+    }
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// Removes every file belonging to a table: bucket, temporary and final files
+pub fn remove_table_files(path: &Path, table_index: usize) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let prefix = format!("table{}_", table_index);
+    for entry in read_dir(path)
+        .context(format!("Could not read directory: {:?}", path))?
+        .filter_map(Result::ok)
+        .map(|x| x.path())
+        .filter(|e| {
+            e.is_file()
+                && e.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&prefix))
+        })
+    {
+        remove_file(&entry).context(format!("Could not remove {:?}", entry))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write_table(path: &Path, table_index: usize, content: &[u8]) {
+        File::create(path.join(format!(table_final_filename_format!(), table_index)))
+            .unwrap()
+            .write_all(content)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = TempDir::new("spaceframe_checkpoint").unwrap();
+        write_table(dir.path(), 1, b"table 1 content");
+
+        let mut manifest = CheckpointManifest::new(12, [3; 32]);
+        manifest.add_table(1, dir.path()).unwrap();
+        manifest.save(dir.path()).unwrap();
+
+        let loaded = CheckpointManifest::load(dir.path()).unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.tables[0].file_size, 15);
+
+        CheckpointManifest::remove(dir.path()).unwrap();
+        assert!(CheckpointManifest::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_first_incomplete_table() {
+        let dir = TempDir::new("spaceframe_checkpoint").unwrap();
+        let mut manifest = CheckpointManifest::new(12, [3; 32]);
+        for table_index in 1..=4 {
+            write_table(dir.path(), table_index, &[table_index as u8; 100]);
+            manifest.add_table(table_index, dir.path()).unwrap();
+        }
+
+        assert_eq!(manifest.first_incomplete_table(dir.path()).unwrap(), 5);
+
+        // Same size but different content
+        write_table(dir.path(), 3, &[0; 100]);
+        assert_eq!(manifest.first_incomplete_table(dir.path()).unwrap(), 3);
+        assert_eq!(manifest.tables.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_table_files() {
+        let dir = TempDir::new("spaceframe_checkpoint").unwrap();
+        for name in [
+            "table2_bucket_0",
+            "table2_bucket_1",
+            "table2_final",
+            "table3_final",
+        ]
+        .iter()
+        {
+            File::create(dir.path().join(name)).unwrap();
+        }
+
+        remove_table_files(dir.path(), 2).unwrap();
+
+        assert!(!dir.path().join("table2_bucket_0").exists());
+        assert!(!dir.path().join("table2_bucket_1").exists());
+        assert!(!dir.path().join("table2_final").exists());
+        assert!(dir.path().join("table3_final").exists());
+    }
+}
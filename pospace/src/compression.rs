@@ -0,0 +1,192 @@
+use crate::config::PlottingConfig;
+use crate::constants::PARAM_EXT;
+use crate::error::{PoSpaceError, SortError, StorageError};
+use crate::storage::{serialize_compressed, ChunkReader, CompressedEntry, PlotEntry};
+use crate::utils::square_to_line_point;
+use crate::{table_final_filename_format, table_tmp_filename_format};
+use anyhow::{Context, Result};
+use std::fs::{remove_file, rename, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Rewrites a pruned table from `input_path` with its compressed representation in `output_path`
+///
+/// The order of the entries is kept, so the left entry of a pair is always
+/// the one with the smallest position and the line points are enough to
+/// retrieve both entries.
+pub fn compress_table(
+    table_index: usize,
+    input_path: &Path,
+    output_path: &Path,
+    k: usize,
+    config: &PlottingConfig,
+) -> Result<()> {
+    let tmp_path = output_path.join(format!(table_tmp_filename_format!(), table_index));
+    let mut chunk_reader = ChunkReader::new(input_path, table_index, k, config)
+        .context(format!("Could not read table {}", table_index))?;
+    let mut output = BufWriter::new(
+        File::create(&tmp_path).context(format!("Could not create file {:?}", tmp_path))?,
+    );
+
+    loop {
+        match chunk_reader.read_chunk() {
+            Ok(entries) => {
+                let compressed_entries = entries
+                    .iter()
+                    .map(|entry| compress_entry(entry, table_index))
+                    .collect::<Result<Vec<CompressedEntry>>>()?;
+                output
+                    .write_all(&serialize_compressed(&compressed_entries, table_index, k)?)
+                    .context(format!(
+                        "Could not write compressed table {} to disk",
+                        table_index
+                    ))?;
+            }
+            Err(e) => match e.downcast_ref::<StorageError>() {
+                Some(StorageError::EndOfFile) => break,
+                _ => return Err(e),
+            },
+        }
+    }
+
+    output.flush()?;
+    drop(output);
+    drop(chunk_reader);
+
+    let input_table_path = input_path.join(format!(table_final_filename_format!(), table_index));
+    let output_table_path = output_path.join(format!(table_final_filename_format!(), table_index));
+    if input_table_path != output_table_path {
+        remove_file(&input_table_path)
+            .map_err(|e| SortError::DeleteError(input_table_path.to_owned(), e.kind()))?;
+    }
+
+    rename(&tmp_path, &output_table_path)
+        .map_err(|e| SortError::RenameError(e.kind()))
+        .context(format!(
+            "Could not rename compressed plot file for table {}",
+            table_index
+        ))?;
+
+    Ok(())
+}
+
+fn compress_entry(entry: &PlotEntry, table_index: usize) -> Result<CompressedEntry> {
+    if table_index == 1 {
+        let metadata = entry.metadata.ok_or(PoSpaceError::EmptyMetadata)?;
+        return Ok(CompressedEntry {
+            x: Some(metadata as u64),
+            fx: None,
+            line_point: None,
+        });
+    }
+
+    let position = entry.position.ok_or(PoSpaceError::EmptyPosition)?;
+    let offset = entry.offset.ok_or(PoSpaceError::EmptyOffset)?;
+    let line_point = Some(square_to_line_point(position + offset, position));
+
+    if table_index == 7 {
+        Ok(CompressedEntry {
+            x: None,
+            fx: Some(entry.fx >> PARAM_EXT),
+            line_point,
+        })
+    } else {
+        Ok(CompressedEntry {
+            x: None,
+            fx: None,
+            line_point,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::{deserialize_compressed, store_table_part};
+    use std::io::Read;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_compress_table7() {
+        const TEST_K: usize = 12;
+        let dir = TempDir::new("spaceframe_compression").unwrap();
+
+        let table7 = vec![
+            PlotEntry {
+                fx: 0b101 << PARAM_EXT | 3,
+                metadata: Some(0),
+                position: Some(4),
+                offset: Some(2),
+            },
+            PlotEntry {
+                fx: 0b111 << PARAM_EXT,
+                metadata: Some(0),
+                position: Some(4000),
+                offset: Some(1),
+            },
+        ];
+        store_table_part(&table7, &dir.path().join("table7_final"), 7, TEST_K).unwrap();
+
+        compress_table(
+            7,
+            dir.path(),
+            dir.path(),
+            TEST_K,
+            &PlottingConfig::default(),
+        )
+        .unwrap();
+
+        let mut buffer = Vec::new();
+        File::open(dir.path().join("table7_final"))
+            .unwrap()
+            .read_to_end(&mut buffer)
+            .unwrap();
+        let entries = deserialize_compressed(&buffer, 7, TEST_K).unwrap();
+
+        assert_eq!(entries[0].fx, Some(0b101));
+        assert_eq!(entries[0].line_point, Some(square_to_line_point(6, 4)));
+        assert_eq!(entries[1].fx, Some(0b111));
+        assert_eq!(
+            entries[1].line_point,
+            Some(square_to_line_point(4001, 4000))
+        );
+    }
+
+    #[test]
+    fn test_compress_table1() {
+        const TEST_K: usize = 12;
+        let dir = TempDir::new("spaceframe_compression").unwrap();
+
+        let table1 = (0..10)
+            .map(|x| PlotEntry {
+                fx: x,
+                metadata: Some(x as u128 * 300),
+                position: None,
+                offset: None,
+            })
+            .collect::<Vec<PlotEntry>>();
+        store_table_part(&table1, &dir.path().join("table1_final"), 1, TEST_K).unwrap();
+
+        compress_table(
+            1,
+            dir.path(),
+            dir.path(),
+            TEST_K,
+            &PlottingConfig::default(),
+        )
+        .unwrap();
+
+        let mut buffer = Vec::new();
+        File::open(dir.path().join("table1_final"))
+            .unwrap()
+            .read_to_end(&mut buffer)
+            .unwrap();
+        let entries = deserialize_compressed(&buffer, 1, TEST_K).unwrap();
+
+        assert_eq!(
+            entries.iter().map(|e| e.x.unwrap()).collect::<Vec<u64>>(),
+            (0..10).map(|x| x * 300).collect::<Vec<u64>>()
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::bitfield::Bitfield;
+use crate::error::PoSpaceError;
+use crate::storage::PlotEntry;
+use anyhow::Result;
+use std::mem::size_of;
+use std::thread::available_parallelism;
+
+/// Default memory budget of a plot job: 1 GiB
+pub const DEFAULT_MEMORY_BUDGET: usize = 1 << 30;
+/// Default minimum number of buckets of a table, more are used when a bucket
+/// would not fit in the memory budget
+pub const DEFAULT_BUCKETS: usize = 128;
+
+/// Resources a plot job is allowed to use
+///
+/// The fields can only be set through validating methods, so a config given to
+/// the plotting is always valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlottingConfig {
+    /// Maximum amount of memory in bytes used by the buffers of the plotting,
+    /// including the bitfield of the used entries kept by phase 2
+    memory_budget: usize,
+    /// Number of threads used to compute the tables
+    threads: usize,
+    /// Minimum number of buckets used to sort the tables on disk, must be a power of two
+    buckets: usize,
+}
+
+impl PlottingConfig {
+    pub fn new(memory_budget: usize, threads: usize, buckets: usize) -> Result<Self> {
+        PlottingConfig::default()
+            .with_memory_budget(memory_budget)?
+            .with_threads(threads)?
+            .with_buckets(buckets)
+    }
+
+    pub fn with_memory_budget(mut self, memory_budget: usize) -> Result<Self> {
+        if memory_budget == 0 {
+            return Err(
+                PoSpaceError::InvalidPlottingConfig("memory budget must not be zero").into(),
+            );
+        }
+        self.memory_budget = memory_budget;
+        Ok(self)
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Result<Self> {
+        if threads == 0 {
+            return Err(
+                PoSpaceError::InvalidPlottingConfig("at least one thread is needed").into(),
+            );
+        }
+        self.threads = threads;
+        Ok(self)
+    }
+
+    pub fn with_buckets(mut self, buckets: usize) -> Result<Self> {
+        if buckets < 2 || !buckets.is_power_of_two() {
+            return Err(PoSpaceError::InvalidPlottingConfig(
+                "the number of buckets must be a power of two greater than 1",
+            )
+            .into());
+        }
+        self.buckets = buckets;
+        Ok(self)
+    }
+
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn buckets(&self) -> usize {
+        self.buckets
+    }
+
+    /// Number of entries of `entry_size` bytes that fit in the memory budget
+    ///
+    /// A chunk is in memory as entries along with its serialized bytes, room is
+    /// left for the entries of a second chunk waiting to be processed, at least
+    /// one entry is always returned.
+    pub fn entries_per_chunk(&self, entry_size: usize) -> usize {
+        (self.memory_budget / (entry_size + 2 * size_of::<PlotEntry>())).max(1)
+    }
+
+    /// Config of the chunks read by phase 2 while the bitfield of the used
+    /// entries of a table of `entries_count` entries is in memory
+    ///
+    /// The bitfield cannot be split, the config is rejected if the budget has
+    /// no room left for the chunks once the bitfield is charged.
+    pub fn backprop_config(&self, entries_count: usize) -> Result<Self> {
+        let bitfield_size = Bitfield::memory_size(entries_count);
+        if bitfield_size >= self.memory_budget {
+            return Err(PoSpaceError::InvalidPlottingConfig(
+                "memory budget too small for the bitfields of phase 2",
+            )
+            .into());
+        }
+        self.with_memory_budget(self.memory_budget - bitfield_size)
+    }
+}
+
+impl Default for PlottingConfig {
+    fn default() -> Self {
+        PlottingConfig {
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            threads: available_parallelism().map(|n| n.get()).unwrap_or(1),
+            buckets: DEFAULT_BUCKETS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(PlottingConfig::new(0, 1, 128).is_err());
+        assert!(PlottingConfig::new(1 << 20, 0, 128).is_err());
+        assert!(PlottingConfig::new(1 << 20, 1, 100).is_err());
+        assert!(PlottingConfig::new(1 << 20, 1, 1).is_err());
+        assert!(PlottingConfig::new(1 << 20, 4, 64).is_ok());
+
+        let config = PlottingConfig::default();
+        assert!(config.with_buckets(0).is_err());
+        assert!(config.with_buckets(96).is_err());
+        assert!(config.with_threads(0).is_err());
+        assert!(config.with_memory_budget(0).is_err());
+        assert_eq!(config.with_buckets(32).unwrap().buckets(), 32);
+    }
+
+    #[test]
+    fn test_entries_per_chunk() {
+        let config = PlottingConfig::new(1 << 20, 1, 128).unwrap();
+        let entries = config.entries_per_chunk(40);
+        assert!(entries * (40 + 2 * size_of::<PlotEntry>()) <= 1 << 20);
+        assert!((entries + 1) * (40 + 2 * size_of::<PlotEntry>()) > 1 << 20);
+
+        let tiny = PlottingConfig::new(1, 1, 128).unwrap();
+        assert_eq!(tiny.entries_per_chunk(40), 1);
+    }
+
+    #[test]
+    fn test_backprop_config() {
+        let config = PlottingConfig::new(1 << 20, 1, 128).unwrap();
+        let backprop_config = config.backprop_config(1 << 20).unwrap();
+        assert_eq!(
+            backprop_config.memory_budget(),
+            (1 << 20) - Bitfield::memory_size(1 << 20)
+        );
+        assert!(config.backprop_config(1 << 23).is_err());
+    }
+}
@@ -4,3 +4,10 @@ pub const PARAM_B: u64 = 119;
 pub const PARAM_C: u64 = 127;
 pub const PARAM_BC: u64 = PARAM_B * PARAM_C;
 pub const STATE_SIZE_BITS: usize = 512;
+/// Number of leading zero bits required by the plot filter, a plot is looked
+/// at for one challenge out of 2^PARAM_PLOT_FILTER
+pub const PARAM_PLOT_FILTER: usize = 9;
+/// Bounds of the space parameter k, the metadata of the entries must fit in
+/// 128 bits (4k bits in tables 4 and 5)
+pub const MIN_K: usize = 12;
+pub const MAX_K: usize = 32;
@@ -2,377 +2,871 @@ use crossbeam_channel::bounded;
 use log::*;
 use rayon::prelude::*;
 use std::{
-    fs::{create_dir_all, remove_dir_all},
+    fs::{create_dir_all, remove_file},
     path::Path,
 };
 
-use crate::storage::{plotentry_size, store_raw_table_part, ChunkReader, PlotEntry};
+use crate::backprop::backpropagate_table;
+use crate::checkpoint::{remove_table_files, CheckpointManifest};
+use crate::compression::compress_table;
+use crate::config::PlottingConfig;
+use crate::plot::{plot_filename, PlotFile, PlotHeader};
+use crate::plot_id::PlotId;
+use crate::progress::{CancellationToken, NoProgress, ProgressSink, ProgressTracker};
+use crate::storage::{move_file, plotentry_size};
+use crate::storage::{ChunkReader, CompressedEntry, PlotEntry};
+use crate::table_final_filename_format;
+use crate::utils::line_point_to_square;
 use crate::{
-    bits::BitsWrapper,
-    constants::{PARAM_BC, PARAM_EXT},
+    constants::{MAX_K, MIN_K, PARAM_BC},
     f1_calculator::F1Calculator,
     fx_calculator::FxCalculator,
-    storage::ENTRIES_PER_CHUNK,
     BitsSlice,
 };
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::thread;
 
-use crate::bits::{from_bits, to_bits};
-use crate::error::{PoSpaceError, StorageError};
-use crate::sort::sort_table_on_disk;
-use crate::table_final_filename_format;
+use crate::bits::from_bits;
+use crate::error::{PlotError, PoSpaceError};
+use crate::sort::{
+    bucket_count, buckets_size, sort_table_on_disk, sort_table_streaming, BucketWriter,
+};
 use anyhow::{Context, Result};
-use bitvec::view::BitView;
 use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+pub const NUMBER_OF_TABLES: usize = 7;
+
+/// Number of table 1 entries waiting to be written to disk
+const F1_CHANNEL_CAPACITY: usize = 1 << 16;
 
-const NUMBER_OF_TABLES: usize = 7;
+/// Number of consecutive x whose f1 values are computed at once
+const F1_RANGE_SIZE: u64 = 1 << 12;
+
+/// Largest number of pairs of adjacent buckets matched in parallel at once
+const MATCH_BATCH_SIZE: usize = 1024;
+
+/// Number of sorted buckets waiting to be matched with the next table
+const SORTED_BUCKETS_CHANNEL_CAPACITY: usize = 1;
+
+/// Number of equal parts of the memory budget while a table is computed from
+/// the sorted buckets of the previous one:
+/// - the bucket being sorted, whose part bounds the size of the sorted buckets
+/// - the sorted bucket waiting in the channel and the one being matched, the
+///   entries of a sorted bucket take at most half of its part
+/// - the batch of bucket pairs being matched and their matches
+/// - the buffers of the bucket writer
+const MEMORY_BUDGET_PARTS: usize = 4;
 
 pub type PlotSeed = [u8; 32];
 
 #[derive(Debug, Clone)]
 pub struct PoSpace {
+    pub plot_id: PlotId,
     pub plot_seed: PlotSeed,
     pub k: usize,
     f1_calculator: F1Calculator,
-    data_path: PathBuf,
+    config: PlottingConfig,
+    progress: ProgressTracker,
+    cancellation: CancellationToken,
+    tmp_path: PathBuf,
+    tmp2_path: PathBuf,
+    plot_path: PathBuf,
+    table7_index: Arc<OnceLock<Vec<u64>>>,
+    plot_file: Arc<OnceLock<PlotFile>>,
 }
 
 impl PoSpace {
-    pub fn new(k: usize, plot_seed: PlotSeed, data_path: &Path) -> Result<Self> {
-        if k < 12 || k > 50 {
+    pub fn new(k: usize, plot_id: PlotId, data_path: &Path) -> Result<Self> {
+        if !(MIN_K..=MAX_K).contains(&k) {
             return Err(PoSpaceError::InvalidK(k).into());
         }
+        let plot_seed = plot_id.plot_seed();
         Ok(PoSpace {
+            plot_id,
             plot_seed,
             k,
             f1_calculator: F1Calculator::new(k, plot_seed),
-            data_path: data_path.to_owned(),
+            config: PlottingConfig::default(),
+            progress: ProgressTracker::new(Arc::new(NoProgress)),
+            cancellation: CancellationToken::new(),
+            tmp_path: data_path.to_owned(),
+            tmp2_path: data_path.to_owned(),
+            plot_path: data_path.join(plot_filename(k, &plot_seed)),
+            table7_index: Arc::new(OnceLock::new()),
+            plot_file: Arc::new(OnceLock::new()),
         })
     }
 
+    /// Uses different directories for the intermediate files and the final plot
+    ///
+    /// The tables of phases 1 and 2 are written in `tmp_path`, the compressed
+    /// tables and the plot file are written in `tmp2_path` (or `tmp_path` if
+    /// missing) and the finished plot is then moved to `final_path`.
+    pub fn with_directories(
+        mut self,
+        tmp_path: &Path,
+        tmp2_path: Option<&Path>,
+        final_path: &Path,
+    ) -> Self {
+        self.tmp_path = tmp_path.to_owned();
+        self.tmp2_path = tmp2_path.unwrap_or(tmp_path).to_owned();
+        self.plot_path = final_path.join(plot_filename(self.k, &self.plot_seed));
+        self.plot_file = Arc::new(OnceLock::new());
+        self
+    }
+
+    /// Limits the memory and the threads used by the plotting
+    pub fn with_config(mut self, config: PlottingConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Reports the progress of the plotting to the sink
+    pub fn with_progress(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = ProgressTracker::new(sink);
+        self
+    }
+
+    /// Stops the plotting once the token is cancelled, the cancellation is checked
+    /// between the chunks of phase 1 and between the tables of the other phases
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Opens an existing plot, k and the plot id are read from its header
+    pub fn open(plot_path: &Path) -> Result<Self> {
+        let plot_file = PlotFile::open(plot_path)?;
+        let data_path = plot_path.parent().unwrap_or_else(|| Path::new(""));
+        let mut pospace = PoSpace::new(
+            plot_file.header.k as usize,
+            plot_file.header.plot_id,
+            data_path,
+        )?;
+        pospace.plot_path = plot_path.to_owned();
+        pospace.plot_file.get_or_init(|| plot_file);
+        Ok(pospace)
+    }
+
+    /// Path of the plot file, it only exists once the plotting is done
+    pub fn plot_path(&self) -> &Path {
+        &self.plot_path
+    }
+
+    /// Header of the plot file, read along with the plot file by the lookups
+    pub fn plot_header(&self) -> Result<&PlotHeader> {
+        Ok(&self.open_plot_file()?.header)
+    }
+
+    /// Plot file read by the lookups, it is opened by the first lookup and then
+    /// shared by the next ones and the clones of the `PoSpace`
+    fn open_plot_file(&self) -> Result<&PlotFile> {
+        if let Some(plot_file) = self.plot_file.get() {
+            return Ok(plot_file);
+        }
+        let plot_file = PlotFile::open(&self.plot_path)?;
+        if plot_file.header.k as usize != self.k || plot_file.header.plot_id != self.plot_id {
+            return Err(PlotError::HeaderMismatch.into());
+        }
+        Ok(self.plot_file.get_or_init(|| plot_file))
+    }
+
+    /// Config of one part of the memory budget, see `MEMORY_BUDGET_PARTS`
+    fn budget_part(&self) -> Result<PlottingConfig> {
+        self.config
+            .with_memory_budget((self.config.memory_budget() / MEMORY_BUDGET_PARTS).max(1))
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancellation.is_cancelled() {
+            return Err(PoSpaceError::Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Size of the final file of a table in the given directory
+    fn table_file_size(path: &Path, table_index: usize) -> u64 {
+        path.join(format!(table_final_filename_format!(), table_index))
+            .metadata()
+            .map(|m| m.len())
+            .unwrap_or(0)
+    }
+
+    /// Removes the intermediate files of every table and the checkpoint manifest
+    fn remove_temporary_files(&self) -> Result<()> {
+        for table_index in 1..=NUMBER_OF_TABLES {
+            remove_table_files(&self.tmp_path, table_index)?;
+            remove_table_files(&self.tmp2_path, table_index)?;
+        }
+        let tmp_plot_path = self.tmp2_path.join(plot_filename(self.k, &self.plot_seed));
+        if tmp_plot_path != self.plot_path && tmp_plot_path.exists() {
+            remove_file(&tmp_plot_path).context(format!("Could not remove {:?}", tmp_plot_path))?;
+        }
+        CheckpointManifest::remove(&self.tmp_path)
+    }
+
     pub fn run_phase_1(&self) -> Result<()> {
-        // Clear data folder
-        match remove_dir_all(&self.data_path) {
-            Ok(_) => {
-                info!("Cleaning data folder");
+        create_dir_all(&self.tmp_path).ok();
+        self.progress.phase_started(1);
+
+        let mut manifest = match CheckpointManifest::load(&self.tmp_path) {
+            Ok(manifest) if manifest.k == self.k && manifest.plot_seed == self.plot_seed => {
+                manifest
             }
-            Err(e) => {
-                warn!("Data folder not cleaned: {}", e);
+            _ => CheckpointManifest::new(self.k, self.plot_seed),
+        };
+        let first_table = manifest
+            .first_incomplete_table(&self.tmp_path)
+            .context("Could not check the checkpoint manifest")?;
+
+        if first_table > 1 {
+            info!("Resuming plotting from table {}", first_table);
+        }
+
+        // Clean the files of the tables that are not complete
+        for table_index in first_table..=NUMBER_OF_TABLES {
+            remove_table_files(&self.tmp_path, table_index).context(format!(
+                "Could not clean intermediate files of table {}",
+                table_index
+            ))?;
+        }
+        manifest
+            .save(&self.tmp_path)
+            .context("Could not write the checkpoint manifest")?;
+
+        for table_index in first_table..=NUMBER_OF_TABLES {
+            self.check_cancelled()?;
+            self.progress.table_started(1, table_index);
+            let streamed = table_index < NUMBER_OF_TABLES;
+
+            // The buckets of the table are computed here only for the first table
+            // of the run, the other ones are computed while the previous table is sorted
+            if table_index == first_table {
+                let share = if streamed { 0.5 } else { 1.0 };
+                let progress = |fraction: f64| {
+                    self.progress
+                        .table_progress(1, table_index, fraction * share)
+                };
+                if table_index == 1 {
+                    self.compute_table_1(&progress)?;
+                } else {
+                    self.compute_table_from_disk(table_index, &progress)?;
+                }
             }
+
+            if streamed {
+                let start = if table_index == first_table { 0.5 } else { 0.0 };
+                info!(
+                    "[Table {}] Sorting table on disk and computing table {} ...",
+                    table_index,
+                    table_index + 1
+                );
+                self.sort_and_compute_next(table_index, &|fraction| {
+                    self.progress
+                        .table_progress(1, table_index, start + fraction * (1.0 - start))
+                })?;
+            } else {
+                info!("[Table {}] Sorting table on disk ...", table_index);
+                sort_table_on_disk(table_index, &self.tmp_path, &self.budget_part()?, self.k)
+                    .context(format!("Could not sort table {} on disk", table_index))?;
+            }
+            info!("[Table {}] Sorting table on disk done", table_index);
+
+            manifest
+                .add_table(table_index, &self.tmp_path)
+                .context(format!(
+                    "Could not create checkpoint for table {}",
+                    table_index
+                ))?;
+            manifest
+                .save(&self.tmp_path)
+                .context("Could not write the checkpoint manifest")?;
+            info!("[Table {}] Table ready", table_index);
+            self.progress.table_finished(
+                1,
+                table_index,
+                Self::table_file_size(&self.tmp_path, table_index),
+            );
         }
-        create_dir_all(&self.data_path).ok();
-        info!("Data dir cleaned and created");
 
+        Ok(())
+    }
+
+    fn compute_table_1(&self, progress: &dyn Fn(f64)) -> Result<()> {
         let table_size = 1u64 << self.k;
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(self.config.threads())
+            .build()
+            .context("Could not create the thread pool")?;
 
         info!("[Table 1] Calculating buckets ...");
 
-        rayon::scope(|s| -> Result<()> {
-            let (sender, receiver) = bounded(*ENTRIES_PER_CHUNK);
-
-            s.spawn(|_| {
-                (0..table_size)
-                    .into_par_iter()
-                    .for_each_with(sender, |s, x| {
-                        let x_wrapped = BitsWrapper::from(x, self.k);
-                        let fx = self.f1_calculator.calculate_f1(&x_wrapped).unwrap();
-                        s.send((BitsWrapper::new(fx), x_wrapped)).unwrap();
-                    });
+        // The entries are computed by the thread pool while the current thread
+        // writes them to disk, so this also works with a single thread
+        thread::scope(|s| -> Result<()> {
+            let (sender, receiver) = bounded(F1_CHANNEL_CAPACITY);
+
+            s.spawn(|| {
+                thread_pool.install(|| {
+                    // Each task computes a range of x sharing the same keystream
+                    (0..table_size.div_ceil(F1_RANGE_SIZE))
+                        .into_par_iter()
+                        .try_for_each_with(sender, |s, range| {
+                            let start = range * F1_RANGE_SIZE;
+                            let count = F1_RANGE_SIZE.min(table_size - start);
+                            let fx = self.f1_calculator.calculate_f1_range(start, count);
+                            (start..).zip(fx).try_for_each(|(x, fx)| s.send((fx, x)))
+                        })
+                })
             });
 
-            let mut buffer = Vec::new();
+            // Besides the channel, the writer has the whole budget, but the buckets
+            // are sized for the part of the budget they are sorted with
+            let buckets = bucket_count(1, self.k, &self.budget_part()?);
+            let mut bucket_writer =
+                BucketWriter::new(&self.tmp_path, 1, self.k, buckets, &self.config);
             let mut counter = 0;
 
             while let Ok(data) = receiver.recv() {
-                buffer.push(PlotEntry {
-                    fx: data.0.value,
-                    metadata: Some(data.1.bits.as_raw_slice().to_vec()),
-                    position: None,
-                    offset: None,
-                });
-
-                if buffer.len() % (1024 * 1024 * 4) == 0 {
+                bucket_writer
+                    .push(PlotEntry {
+                        fx: data.0,
+                        metadata: Some(data.1 as u128),
+                        position: None,
+                        offset: None,
+                    })
+                    .context("Failed to store an entry of table 1")?;
+                counter += 1;
+
+                if counter % F1_CHANNEL_CAPACITY as u64 == 0 {
+                    self.check_cancelled()?;
+                    progress(counter as f64 / table_size as f64);
+                }
+                if counter % (1024 * 1024 * 4) == 0 {
                     info!(
                         "[Table 1] Calculating progess: {:.3}%",
-                        (buffer.len() + counter * *ENTRIES_PER_CHUNK) as f64
-                            / (table_size as usize) as f64
-                            * 100 as f64
+                        counter as f64 / table_size as f64 * 100.0
                     );
                 }
+            }
 
-                if buffer.len() == *ENTRIES_PER_CHUNK {
-                    counter += 1;
-                    info!("[Table 1] Wrinting part {} to disk ...", counter);
-                    store_raw_table_part(1, counter, &buffer, &self.data_path)
-                        .context(format!("Failed to store part {} of table 1", counter))?;
-                    buffer.clear();
+            info!("[Table 1] Writing buckets to disk ...");
+            bucket_writer
+                .finish()
+                .context("Failed to store the buckets of table 1")
+        })?;
+
+        Ok(())
+    }
+
+    /// Computes the buckets of a table from the final file of the previous one,
+    /// used when the plotting resumes
+    fn compute_table_from_disk(&self, table_index: usize, progress: &dyn Fn(f64)) -> Result<()> {
+        // The chunks read take the part of the buckets being sorted
+        let chunk_reader = ChunkReader::new(
+            &self.tmp_path,
+            table_index - 1,
+            self.k,
+            &self.budget_part()?,
+        )
+        .context("Could not create chunk reader")?;
+        let previous_entries = chunk_reader.entries_count as u64;
+        self.compute_table(table_index, chunk_reader, previous_entries, progress)
+    }
+
+    /// Sorts the buckets of a table in a separate thread while the next table is
+    /// computed from the sorted buckets, the final file of the table is still
+    /// written for the next phases but never read back
+    fn sort_and_compute_next(&self, table_index: usize, progress: &dyn Fn(f64)) -> Result<()> {
+        let sort_config = self.budget_part()?;
+        let entries = buckets_size(
+            &self.tmp_path,
+            table_index,
+            bucket_count(table_index, self.k, &sort_config),
+        ) / plotentry_size(table_index, self.k) as u64;
+
+        thread::scope(|s| -> Result<()> {
+            let (sender, receiver) = bounded(SORTED_BUCKETS_CHANNEL_CAPACITY);
+
+            let sorter = s.spawn(move || {
+                sort_table_streaming(
+                    table_index,
+                    &self.tmp_path,
+                    &sort_config,
+                    self.k,
+                    |bucket| {
+                        self.check_cancelled()?;
+                        // The next table may stop early, this table is still sorted to the end
+                        sender.send(bucket).ok();
+                        Ok(())
+                    },
+                )
+            });
+
+            let computed = self.compute_table(
+                table_index + 1,
+                receiver.into_iter().map(Ok),
+                entries,
+                progress,
+            );
+            let sorted = sorter.join().expect("The sorting thread panicked");
+            computed?;
+            sorted.context(format!("Could not sort table {} on disk", table_index))
+        })
+    }
+
+    /// Computes the buckets of a table from the sorted entries of the previous one
+    fn compute_table(
+        &self,
+        table_index: usize,
+        chunks: impl Iterator<Item = Result<Vec<PlotEntry>>>,
+        previous_entries: u64,
+        progress: &dyn Fn(f64),
+    ) -> Result<()> {
+        let table_size = 1u64 << self.k;
+
+        info!("[Table {}] Calculating buckets ...", table_index);
+
+        // The matcher and the writer each take a part of the budget
+        let config = self.budget_part()?;
+        let buckets = bucket_count(table_index, self.k, &config);
+        let mut bucket_writer =
+            BucketWriter::new(&self.tmp_path, table_index, self.k, buckets, &config);
+        // The matches of a batch take about as much memory as its entries
+        let max_batch_entries = config.entries_per_chunk(plotentry_size(table_index - 1, self.k));
+
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(self.config.threads())
+            .build()
+            .context("Could not create the thread pool")?;
+        // Each worker needs its own calculator, find_matches uses its rmap
+        let mut fx_calculators = (0..self.config.threads())
+            .map(|_| FxCalculator::new(self.k, table_index))
+            .collect::<Vec<FxCalculator>>();
+
+        let mut match_counter = 0;
+        let mut bucket = 0;
+        let mut pos = 0;
+        let mut left_bucket = Vec::new();
+        let mut right_bucket = Vec::new();
+        let mut batch = Vec::with_capacity(MATCH_BATCH_SIZE);
+        let mut batch_entries = 0;
+
+        'chunks: for entries in chunks {
+            self.check_cancelled()?;
+            progress(pos as f64 / previous_entries.max(1) as f64);
+            for mut entry in entries? {
+                entry.position = Some(pos);
+                pos += 1;
+
+                let y_bucket = entry.fx / PARAM_BC;
+
+                if y_bucket == bucket {
+                    left_bucket.push(entry);
+                } else if y_bucket == bucket + 1 {
+                    right_bucket.push(entry);
+                } else {
+                    let pair = (
+                        std::mem::take(&mut left_bucket),
+                        std::mem::take(&mut right_bucket),
+                    );
+                    if y_bucket == bucket + 2 {
+                        bucket += 1;
+                        left_bucket = pair.1.clone();
+                        right_bucket.push(entry);
+                    } else {
+                        bucket = y_bucket;
+                        left_bucket.push(entry);
+                    }
+                    if !pair.0.is_empty() && !pair.1.is_empty() {
+                        batch_entries += pair.0.len() + pair.1.len();
+                        batch.push(pair);
+                    }
                 }
-            }
 
-            if buffer.len() > 0 {
-                info!("[Table 1] Wrinting part {} to disk ...", counter);
-                store_raw_table_part(1, counter, &buffer, &self.data_path)
-                    .context(format!("Failed to store part {} of table 1", counter))?;
+                if batch.len() == MATCH_BATCH_SIZE || batch_entries >= max_batch_entries {
+                    let batch = std::mem::take(&mut batch);
+                    batch_entries = 0;
+                    if !self.store_matches(
+                        &thread_pool,
+                        &mut fx_calculators,
+                        batch,
+                        &mut bucket_writer,
+                        &mut match_counter,
+                    )? {
+                        break 'chunks;
+                    }
+                }
             }
+        }
+        self.store_matches(
+            &thread_pool,
+            &mut fx_calculators,
+            batch,
+            &mut bucket_writer,
+            &mut match_counter,
+        )?;
+
+        info!("[Table {}] Writing buckets to disk ...", table_index);
+        bucket_writer.finish().context(format!(
+            "Failed to store the buckets of table {}",
+            table_index
+        ))?;
+
+        info!(
+            "[Table {}] {} matches found ({:.3}% of table 1 size)",
+            table_index,
+            match_counter,
+            (match_counter as f64 / table_size as f64) * 100.0
+        );
+
+        Ok(())
+    }
 
-            Ok(())
+    /// Matches the bucket pairs of the batch in parallel and writes the new entries
+    /// in the order of the pairs, returns false once the table holds too many entries
+    fn store_matches(
+        &self,
+        thread_pool: &ThreadPool,
+        fx_calculators: &mut [FxCalculator],
+        batch: Vec<(Vec<PlotEntry>, Vec<PlotEntry>)>,
+        bucket_writer: &mut BucketWriter,
+        match_counter: &mut usize,
+    ) -> Result<bool> {
+        if batch.is_empty() {
+            return Ok(true);
+        }
+        // Every worker matches a contiguous part of the batch so that the
+        // results can be gathered in order
+        let pairs_per_worker = batch.len().div_ceil(fx_calculators.len());
+        let results = thread_pool.install(|| {
+            fx_calculators
+                .par_iter_mut()
+                .zip(batch.par_chunks(pairs_per_worker))
+                .map(|(fx_calculator, pairs)| {
+                    pairs
+                        .iter()
+                        .map(|(left, right)| self.match_buckets(fx_calculator, left, right))
+                        .collect::<Result<Vec<Vec<PlotEntry>>>>()
+                })
+                .collect::<Result<Vec<Vec<Vec<PlotEntry>>>>>()
         })?;
 
-        info!("[Table 1] Sorting table on disk ...");
-        sort_table_on_disk(1, &self.data_path, *ENTRIES_PER_CHUNK, self.k)
-            .context(format!("Could not sort table {} on disk", 1))?;
-        info!("[Table 1] Sorting table on disk done");
-        info!("[Table 1] Table ready");
-
-        for table_index in 2..=NUMBER_OF_TABLES {
-            info!("[Table {}] Calculating buckets ...", table_index);
-
-            let mut chunk_reader = ChunkReader::new(&self.data_path, table_index - 1, self.k)
-                .context("Could not create chunk reader")?;
-
-            let mut fx_calculator = FxCalculator::new(self.k, table_index);
-            let mut match_counter = 0;
-            let mut bucket = 0;
-            let mut pos = 0;
-            let mut part = 0;
-            let mut left_bucket = Vec::new();
-            let mut right_bucket = Vec::new();
-            let mut buffer_to_write = Vec::new();
-
-            loop {
-                match chunk_reader.read_chunk() {
-                    Ok(entries) => {
-                        for mut left_entry in entries {
-                            left_entry.position = Some(pos);
-
-                            let y_bucket = left_entry.fx / PARAM_BC;
-
-                            if y_bucket == bucket {
-                                left_bucket.push(left_entry);
-                            } else if y_bucket == bucket + 1 {
-                                right_bucket.push(left_entry);
-                            } else {
-                                if !left_bucket.is_empty() && !right_bucket.is_empty() {
-                                    // Check for matches
-                                    let matches =
-                                        fx_calculator.find_matches(&left_bucket, &right_bucket);
-
-                                    match_counter += matches.len();
-
-                                    for match_item in matches {
-                                        let left_entry = &left_bucket[match_item.left_index];
-                                        let right_entry = &right_bucket[match_item.right_index];
-
-                                        let (left_metadata, right_metadata) = (
-                                            left_entry
-                                                .metadata
-                                                .as_ref()
-                                                .ok_or(PoSpaceError::EmptyMetadata)?
-                                                .view_bits()
-                                                [..collation_size_bits(table_index, self.k)]
-                                                .to_bitvec(),
-                                            right_entry
-                                                .metadata
-                                                .as_ref()
-                                                .ok_or(PoSpaceError::EmptyMetadata)?
-                                                .view_bits()
-                                                [..collation_size_bits(table_index, self.k)]
-                                                .to_bitvec(),
-                                        );
-
-                                        assert_eq!(
-                                            left_metadata.len(),
-                                            collation_size_bits(table_index, self.k)
-                                        );
-                                        assert_eq!(
-                                            right_metadata.len(),
-                                            collation_size_bits(table_index, self.k)
-                                        );
-
-                                        let f_output = fx_calculator.calculate_fn(
-                                            &to_bits(left_entry.fx, self.k + PARAM_EXT),
-                                            &left_metadata,
-                                            &right_metadata,
-                                        );
-
-                                        assert_eq!(
-                                            f_output.1.len(),
-                                            collation_size_bits(table_index + 1, self.k)
-                                        );
-
-                                        buffer_to_write.push(PlotEntry {
-                                            fx: from_bits(&f_output.0),
-                                            metadata: Some(f_output.1.as_raw_slice().to_vec()),
-                                            position: Some(
-                                                left_entry
-                                                    .position
-                                                    .ok_or(PoSpaceError::EmptyPosition)?,
-                                            ),
-                                            offset: Some(
-                                                right_entry
-                                                    .position
-                                                    .ok_or(PoSpaceError::EmptyPosition)?
-                                                    - left_entry
-                                                        .position
-                                                        .ok_or(PoSpaceError::EmptyPosition)?,
-                                            ),
-                                        })
-                                    }
-                                }
-
-                                if y_bucket == bucket + 2 {
-                                    bucket += 1;
-                                    left_bucket = right_bucket.clone();
-                                    right_bucket.clear();
-                                    right_bucket.push(left_entry);
-                                } else {
-                                    bucket = y_bucket;
-                                    left_bucket.clear();
-                                    left_bucket.push(left_entry);
-                                    right_bucket.clear();
-                                }
-                            }
-
-                            pos += 1;
-
-                            if match_counter >= (table_size * 2) as usize {
-                                warn!("Too many match, skipping...");
-                                break;
-                            }
-                        }
-
-                        part += 1;
-
-                        if !buffer_to_write.is_empty() {
-                            info!("[Table {}] Writing part {} to disk", table_index, part);
-                            store_raw_table_part(
-                                table_index,
-                                part,
-                                &buffer_to_write,
-                                &self.data_path,
-                            )
-                            .context(format!(
-                                "Failed to store part {} of table {}",
-                                part, table_index
-                            ))?;
-                            buffer_to_write.clear();
-                        }
-
-                        if match_counter >= (table_size * 2) as usize {
-                            break;
-                        }
-                    }
-                    Err(e) => match e.downcast_ref::<StorageError>() {
-                        Some(StorageError::EndOfFile) => break,
-                        _ => return Err(e),
-                    },
-                }
+        let max_matches = 2 << self.k;
+        for mut entries in results.into_iter().flatten() {
+            // The table never holds more than `max_matches` entries
+            let room = max_matches - *match_counter;
+            let full = entries.len() >= room;
+            entries.truncate(room);
+            *match_counter += entries.len();
+            for entry in entries {
+                bucket_writer.push(entry)?;
+            }
+            if full {
+                warn!("Too many match, skipping...");
+                return Ok(false);
             }
+        }
+        Ok(true)
+    }
 
+    /// Entries of the next table given by the matches between two adjacent buckets
+    fn match_buckets(
+        &self,
+        fx_calculator: &mut FxCalculator,
+        left_bucket: &[PlotEntry],
+        right_bucket: &[PlotEntry],
+    ) -> Result<Vec<PlotEntry>> {
+        let metadata = |entry: &PlotEntry| entry.metadata.ok_or(PoSpaceError::EmptyMetadata);
+        let matches = fx_calculator.find_matches(left_bucket, right_bucket);
+        let mut entries = Vec::with_capacity(matches.len());
+
+        for match_item in matches {
+            let left_entry = &left_bucket[match_item.left_index];
+            let right_entry = &right_bucket[match_item.right_index];
+
+            let (fx, output_metadata) = fx_calculator.calculate_fn_int(
+                left_entry.fx,
+                metadata(left_entry)?,
+                metadata(right_entry)?,
+            );
+
+            let left_position = left_entry.position.ok_or(PoSpaceError::EmptyPosition)?;
+            let right_position = right_entry.position.ok_or(PoSpaceError::EmptyPosition)?;
+            entries.push(PlotEntry {
+                fx,
+                metadata: Some(output_metadata),
+                position: Some(left_position),
+                offset: Some(right_position - left_position),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Removes the entries that do not lead to table 7, from table 6 down to table 1
+    pub fn run_phase_2(&self) -> Result<()> {
+        self.progress.phase_started(2);
+        for table_index in (2..=NUMBER_OF_TABLES).rev() {
+            self.check_cancelled()?;
+            self.progress.table_started(2, table_index);
             info!(
-                "[Table {}] {} matches found ({:.3}% of table 1 size)",
+                "[Table {}] Backpropagating to table {} ...",
+                table_index,
+                table_index - 1
+            );
+            backpropagate_table(table_index, &self.tmp_path, self.k, &self.config).context(
+                format!(
+                    "Could not backpropagate table {} to table {}",
+                    table_index,
+                    table_index - 1
+                ),
+            )?;
+            info!("[Table {}] Table pruned", table_index - 1);
+            self.progress.table_finished(
+                2,
                 table_index,
-                match_counter,
-                (match_counter as f64 / table_size as f64) * 100.0
+                Self::table_file_size(&self.tmp_path, table_index - 1),
             );
+        }
+        Ok(())
+    }
 
-            info!("[Table {}] Sorting table on disk ...", table_index);
-            sort_table_on_disk(table_index, &self.data_path, *ENTRIES_PER_CHUNK, self.k)
-                .context(format!("Could not sort table {} on disk", table_index))?;
-            info!("[Table {}] Sorting table on disk done", table_index);
-            info!("[Table {}] Table ready", table_index);
+    /// Compresses tables 1 to 6, only the x values and the line points are kept
+    pub fn run_phase_3(&self) -> Result<()> {
+        create_dir_all(&self.tmp2_path).ok();
+        self.progress.phase_started(3);
+        for table_index in 1..NUMBER_OF_TABLES {
+            self.check_cancelled()?;
+            self.progress.table_started(3, table_index);
+            info!("[Table {}] Compressing table ...", table_index);
+            compress_table(
+                table_index,
+                &self.tmp_path,
+                &self.tmp2_path,
+                self.k,
+                &self.config,
+            )
+            .context(format!("Could not compress table {}", table_index))?;
+            info!("[Table {}] Table compressed", table_index);
+            self.progress.table_finished(
+                3,
+                table_index,
+                Self::table_file_size(&self.tmp2_path, table_index),
+            );
         }
+        Ok(())
+    }
 
+    /// Writes the last table with the first k bits of fx and the line points,
+    /// then gathers all the tables in the plot file
+    pub fn run_phase_4(&self) -> Result<()> {
+        self.progress.phase_started(4);
+        self.check_cancelled()?;
+        self.progress.table_started(4, NUMBER_OF_TABLES);
+        info!("[Table {}] Compressing table ...", NUMBER_OF_TABLES);
+        compress_table(
+            NUMBER_OF_TABLES,
+            &self.tmp_path,
+            &self.tmp2_path,
+            self.k,
+            &self.config,
+        )
+        .context(format!("Could not compress table {}", NUMBER_OF_TABLES))?;
+        info!("[Table {}] Table compressed", NUMBER_OF_TABLES);
+
+        let plot_filename = plot_filename(self.k, &self.plot_seed);
+        let tmp_plot_path = self.tmp2_path.join(&plot_filename);
+        info!("Writing plot file {:?} ...", tmp_plot_path);
+        PlotFile::create(&tmp_plot_path, self.k, self.plot_id, &self.tmp2_path)
+            .context(format!("Could not write plot file {:?}", tmp_plot_path))?;
+        CheckpointManifest::remove(&self.tmp_path)?;
+        let plot_size = tmp_plot_path.metadata().map(|m| m.len()).unwrap_or(0);
+
+        if tmp_plot_path != self.plot_path {
+            info!("Moving plot file to {:?} ...", self.plot_path);
+            if let Some(final_path) = self.plot_path.parent() {
+                create_dir_all(final_path).ok();
+            }
+            move_file(&tmp_plot_path, &self.plot_path)
+                .context(format!("Could not move plot file to {:?}", self.plot_path))?;
+        }
+        info!("Plot ready");
+        self.progress.table_finished(4, NUMBER_OF_TABLES, plot_size);
         Ok(())
     }
 
-    pub fn find_xvalues_from_target(&self, target: &BitsSlice) -> Result<Vec<Vec<PlotEntry>>> {
+    /// Runs all the phases to create the plot
+    ///
+    /// If the plotting is cancelled, the intermediate files are removed and
+    /// `PoSpaceError::Cancelled` is returned. A memory budget too small for the
+    /// bitfields of phase 2 is rejected before phase 1 starts.
+    pub fn plot(&self) -> Result<()> {
+        self.config.backprop_config(1 << self.k)?;
+
+        let result = self
+            .run_phase_1()
+            .context("Failed to run phase 1 of plotting")
+            .and_then(|_| {
+                self.run_phase_2()
+                    .context("Failed to run phase 2 of plotting")
+            })
+            .and_then(|_| {
+                self.run_phase_3()
+                    .context("Failed to run phase 3 of plotting")
+            })
+            .and_then(|_| {
+                self.run_phase_4()
+                    .context("Failed to run phase 4 of plotting")
+            });
+
+        if let Err(e) = &result {
+            if matches!(e.downcast_ref(), Some(PoSpaceError::Cancelled)) {
+                info!("Plotting cancelled, removing the intermediate files ...");
+                self.remove_temporary_files()
+                    .context("Could not remove the intermediate files")?;
+            }
+        }
+        result
+    }
+
+    /// Entries of table 7 whose first k bits of fx are equal to the target,
+    /// the index of table 7 is only read by the first lookup
+    fn find_table7_entries(
+        &self,
+        plot_file: &PlotFile,
+        target: u64,
+    ) -> Result<Vec<CompressedEntry>> {
+        let index = match self.table7_index.get() {
+            Some(index) => index,
+            None => {
+                let index = plot_file.read_index()?;
+                self.table7_index.get_or_init(|| index)
+            }
+        };
+        plot_file.find_table7_entries(index, target)
+    }
+
+    /// Returns the two x values of a single branch of every proof matching the target
+    ///
+    /// The branch is chosen by the bits of `quality_index` (less than 32), the most
+    /// significant bit giving the side taken in table 6. Only one entry is read in
+    /// tables 6 to 2, then the two x values in table 1.
+    pub fn find_quality_xvalues(
+        &self,
+        target: &BitsSlice,
+        quality_index: usize,
+    ) -> Result<Vec<[u64; 2]>> {
+        assert_eq!(target.len(), self.k);
+        assert!(quality_index < 1 << (NUMBER_OF_TABLES - 2));
+
+        let plot_file = self.open_plot_file()?;
+        let mut xvalues = Vec::new();
+
+        for table7_entry in self.find_table7_entries(plot_file, from_bits(target))? {
+            let mut line_point = table7_entry
+                .line_point
+                .ok_or(PoSpaceError::EmptyLinePoint)?;
+
+            // Going from table 6 to table 2
+            for i in (2..NUMBER_OF_TABLES).rev() {
+                let (right, left) = line_point_to_square(line_point);
+                let position = if quality_index >> (i - 2) & 1 == 0 {
+                    left
+                } else {
+                    right
+                };
+                line_point = plot_file
+                    .read_entry(i, position)
+                    .context(format!(
+                        "Could not read entry at position {} in table {}",
+                        position, i
+                    ))?
+                    .line_point
+                    .ok_or(PoSpaceError::EmptyLinePoint)?;
+            }
+
+            let (right, left) = line_point_to_square(line_point);
+            let mut pair = [0; 2];
+            for (x, position) in pair.iter_mut().zip([left, right].iter()) {
+                *x = plot_file
+                    .read_entry(1, *position)
+                    .context(format!(
+                        "Could not read entry at position {} in table 1",
+                        position
+                    ))?
+                    .x
+                    .ok_or(PoSpaceError::EmptyXValue)?;
+            }
+            xvalues.push(pair);
+        }
+        Ok(xvalues)
+    }
+
+    /// Returns the x values of every proof matching the target
+    pub fn find_xvalues_from_target(&self, target: &BitsSlice) -> Result<Vec<Vec<u64>>> {
         assert_eq!(target.len(), self.k);
 
-        let mut chunk_reader = ChunkReader::new(&self.data_path, 7, self.k)
-            .context("Could not create chunk reader")?;
-        let mut proofs = Vec::new();
+        let plot_file = self.open_plot_file()?;
+        self.find_table7_entries(plot_file, from_bits(target))?
+            .iter()
+            .map(|table7_entry| Self::find_xvalues(plot_file, table7_entry))
+            .collect()
+    }
 
-        loop {
-            match chunk_reader.read_chunk() {
-                Ok(entries) => {
-                    let potential_proof_entries: Vec<PlotEntry> = entries
-                        .into_par_iter()
-                        .filter(|entry| to_bits(entry.fx, self.k + PARAM_EXT)[..self.k] == target)
-                        .collect();
-
-                    for table7_entry in potential_proof_entries {
-                        let mut entries_buffer = Vec::new();
-                        let mut temp_buffer = Vec::new();
-                        entries_buffer.push(table7_entry);
-
-                        // Going from table 6 to table 1
-                        for i in (1..=6).rev() {
-                            for entry in &entries_buffer {
-                                let pos = entry.position.ok_or(PoSpaceError::EmptyPosition)?;
-                                let offset = entry.offset.ok_or(PoSpaceError::EmptyOffset)?;
-                                let entry_size = plotentry_size(i, self.k);
-
-                                let mut table_i = File::open(
-                                    self.data_path
-                                        .join(format!(table_final_filename_format!(), i)),
-                                )?;
-
-                                let mut buffer = vec![0u8; entry_size];
-
-                                // Retrieve left entry
-                                table_i
-                                    .seek(SeekFrom::Start(pos * entry_size as u64))
-                                    .context(format!(
-                                        "Could not seek to left entry at position {} in table {}",
-                                        pos, i
-                                    ))?;
-                                table_i.read_exact(&mut buffer).context(format!(
-                                    "Could not read left entry at position {} in table {}",
-                                    pos, i
-                                ))?;
-                                let left_entry: PlotEntry = bincode::deserialize(&buffer)
-                                    .or(Err(StorageError::DeserializationError))
-                                    .context(format!(
-                                        "Could not deserialize left entry in table {}",
-                                        i
-                                    ))?;
-
-                                // Retrieve right entry
-                                table_i
-                                    .seek(SeekFrom::Start((pos + offset) * entry_size as u64))
-                                    .context(format!(
-                                        "Could not seek to right entry at position {} with offset {} in table {}",
-                                        pos, offset, i
-                                    ))?;
-                                table_i.read_exact(&mut buffer).context(format!(
-                                    "Could not read right entry at position {} with offset {} in table {}",
-                                    pos, offset, i
-                                ))?;
-                                let right_entry: PlotEntry = bincode::deserialize(&buffer)
-                                    .or(Err(StorageError::DeserializationError))
-                                    .context(format!(
-                                        "Could not deserialize right entry in table {}",
-                                        i
-                                    ))?;
-
-                                temp_buffer.push(left_entry);
-                                temp_buffer.push(right_entry);
-                            }
-                            entries_buffer.clear();
-                            entries_buffer.append(&mut temp_buffer);
-                        }
-                        proofs.push(entries_buffer);
-                    }
+    /// Returns the x values of the proof at `index` among the proofs matching the
+    /// target, in the same order as `find_xvalues_from_target`
+    ///
+    /// Only the entries of this proof are read, `None` is returned if there are
+    /// not enough proofs.
+    pub fn find_xvalues_at(&self, target: &BitsSlice, index: usize) -> Result<Option<Vec<u64>>> {
+        assert_eq!(target.len(), self.k);
+
+        let plot_file = self.open_plot_file()?;
+        self.find_table7_entries(plot_file, from_bits(target))?
+            .get(index)
+            .map(|table7_entry| Self::find_xvalues(plot_file, table7_entry))
+            .transpose()
+    }
+
+    /// Reads the x values of the proof of an entry of table 7
+    ///
+    /// The x values of each entry are in the order of their positions in the
+    /// previous table, which is the canonical order of the f-values.
+    fn find_xvalues(plot_file: &PlotFile, table7_entry: &CompressedEntry) -> Result<Vec<u64>> {
+        let mut line_points = vec![table7_entry
+            .line_point
+            .ok_or(PoSpaceError::EmptyLinePoint)?];
+
+        // Going from table 6 to table 2
+        for i in (2..NUMBER_OF_TABLES).rev() {
+            let mut temp_buffer = Vec::new();
+            for line_point in &line_points {
+                let (right, left) = line_point_to_square(*line_point);
+                for position in [left, right].iter() {
+                    let entry = plot_file.read_entry(i, *position).context(format!(
+                        "Could not read entry at position {} in table {}",
+                        position, i
+                    ))?;
+                    temp_buffer.push(entry.line_point.ok_or(PoSpaceError::EmptyLinePoint)?);
                 }
-                Err(e) => match e.downcast_ref::<StorageError>() {
-                    Some(StorageError::EndOfFile) => break,
-                    _ => return Err(e),
-                },
             }
+            line_points = temp_buffer;
         }
-        Ok(proofs)
+
+        // Retrieving x values from table 1
+        let mut x_values = Vec::new();
+        for line_point in &line_points {
+            let (right, left) = line_point_to_square(*line_point);
+            for position in [left, right].iter() {
+                let entry = plot_file.read_entry(1, *position).context(format!(
+                    "Could not read entry at position {} in table 1",
+                    position
+                ))?;
+                x_values.push(entry.x.ok_or(PoSpaceError::EmptyXValue)?);
+            }
+        }
+        Ok(x_values)
     }
 }
 
@@ -390,36 +884,41 @@ pub fn collation_size_bits(table_index: usize, k: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+    use spaceframe_crypto::traits::PrivateKey;
+
+    fn test_plot_id() -> PlotId {
+        let public_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+            .unwrap()
+            .public_key();
+        PlotId::new(public_key, public_key, [0; 32])
+    }
 
     #[test]
     fn test_invalid_k_too_small() {
         const TEST_K: usize = 11;
-        let plot_seed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
-        let pos = PoSpace::new(TEST_K, plot_seed, "test_data".as_ref());
+        let pos = PoSpace::new(TEST_K, test_plot_id(), "test_data".as_ref());
         assert!(pos.is_err());
     }
 
     #[test]
     fn test_invalid_k_too_large() {
-        const TEST_K: usize = 51;
-        let plot_seed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
-        let pos = PoSpace::new(TEST_K, plot_seed, "test_data".as_ref());
+        const TEST_K: usize = MAX_K + 1;
+        let pos = PoSpace::new(TEST_K, test_plot_id(), "test_data".as_ref());
         assert!(pos.is_err());
     }
 
     #[test]
     fn test_valid_k_lower_bound() {
         const TEST_K: usize = 12;
-        let plot_seed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
-        let pos = PoSpace::new(TEST_K, plot_seed, "test_data".as_ref());
+        let pos = PoSpace::new(TEST_K, test_plot_id(), "test_data".as_ref());
         assert!(pos.is_ok());
     }
 
     #[test]
     fn test_valid_k_upper_bound() {
-        const TEST_K: usize = 50;
-        let plot_seed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
-        let pos = PoSpace::new(TEST_K, plot_seed, "test_data".as_ref());
+        const TEST_K: usize = MAX_K;
+        let pos = PoSpace::new(TEST_K, test_plot_id(), "test_data".as_ref());
         assert!(pos.is_ok());
     }
 
@@ -1,10 +1,11 @@
+use crate::constants::{MAX_K, MIN_K};
 use std::io::ErrorKind;
 use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum PoSpaceError {
-    #[error("Space parameter k must be greater than 12 and less than 48, found {0}")]
+    #[error("Space parameter k must be between {} and {}, found {0}", MIN_K, MAX_K)]
     InvalidK(usize),
 
     #[error("Metadata is empty in plot entry")]
@@ -15,6 +16,18 @@ pub enum PoSpaceError {
 
     #[error("Offset is empty in plot entry")]
     EmptyOffset,
+
+    #[error("Line point is empty in plot entry")]
+    EmptyLinePoint,
+
+    #[error("X value is empty in plot entry")]
+    EmptyXValue,
+
+    #[error("Invalid plotting config: {0}")]
+    InvalidPlottingConfig(&'static str),
+
+    #[error("The plotting has been cancelled")]
+    Cancelled,
 }
 
 #[derive(Error, Debug)]
@@ -48,19 +61,64 @@ pub enum StorageError {
 }
 
 #[derive(Error, Debug)]
-pub enum MergeChunkError {
-    #[error("Some chunks are empty while trying to chetch the minimum")]
-    EmptyChunksWhileFetchingMininum,
+pub enum VerifierError {
+    #[error("Space parameter k must be between {} and {}, found {0}", MIN_K, MAX_K)]
+    InvalidK(usize),
+
+    #[error("A proof must contain 64 x-values, found {0}")]
+    InvalidXValuesCount(usize),
+
+    #[error("The x-value {0} does not fit in k bits")]
+    XValueOutOfRange(u64),
+
+    #[error("The challenge must be 32 bytes long, found {0} bytes")]
+    InvalidChallengeLength(usize),
+
+    #[error("The x-values are not in the canonical order in table {0}")]
+    NotCanonical(usize),
+
+    #[error("The x-values do not match in table {0}")]
+    NotMatching(usize),
+
+    #[error("The proof does not match the challenge")]
+    ChallengeMismatch,
 
-    #[error("The minimum chunk is empty")]
-    MinChunkIsEmpty,
+    #[error("The plot of the proof does not pass the plot filter")]
+    PlotFilterNotPassed,
 }
 
 #[derive(Error, Debug)]
-pub enum VerifierError {
-    #[error("A proof must contain 64 x-values")]
-    InvalidXValuesCount,
+pub enum PlotError {
+    #[error("The file is not a plot")]
+    InvalidMagic,
+
+    #[error("The plot header is invalid")]
+    InvalidHeader,
+
+    #[error("Plot format version {0} is not supported")]
+    UnsupportedVersion(u16),
+
+    #[error("Table {0} is invalid or truncated in the plot")]
+    InvalidTable(usize),
+
+    #[error("The index of table 7 is invalid or truncated in the plot")]
+    InvalidIndex,
+
+    #[error("The plot file has {0} bytes, {1} bytes expected")]
+    InvalidFileSize(u64, u64),
+
+    #[error("Position {0} is out of table {1}")]
+    PositionOutOfTable(u64, usize),
+
+    #[error("The plot header does not match k or the plot seed")]
+    HeaderMismatch,
+}
+
+#[derive(Error, Debug)]
+pub enum HarvesterError {
+    #[error("The plot {0:?} is not harvested")]
+    UnknownPlot(PathBuf),
 
-    #[error("The proof is invalid")]
-    InvalidProof,
+    #[error("No proof at index {0} for this challenge")]
+    ProofNotFound(usize),
 }
@@ -65,4 +65,69 @@ impl F1Calculator {
         result.extend_from_bitslice(extension);
         Ok(result)
     }
+
+    /// Values of f1 for the `count` consecutive x starting at `start`
+    ///
+    /// The keystream of the whole range is generated once instead of once per x,
+    /// the results are the same as `calculate_f1`.
+    pub fn calculate_f1_range(&self, start: u64, count: u64) -> Vec<u64> {
+        if count == 0 {
+            return Vec::new();
+        }
+        let k = self.k as u64;
+        let state_size = STATE_SIZE_BITS as u64;
+        let first_offset = start * k / state_size;
+        let last_offset = (start + count - 1) * k / state_size;
+
+        // An output spanning two states reads the state starting one byte further
+        let mut keystream =
+            vec![0; (last_offset - first_offset) as usize + 1 + STATE_SIZE_BITS / 8];
+        let key = Key::from_slice(&self.plot_seed);
+        let nonce = Nonce::from_slice(b"000000000000");
+        let mut cipher = ChaCha8::new(key, nonce);
+        cipher.seek(first_offset);
+        cipher.apply_keystream(&mut keystream);
+
+        (start..start + count)
+            .map(|x| {
+                let (q, r) = divmod(x * k, state_size);
+                let state = &keystream[(q - first_offset) as usize..];
+                let mut fx = 0;
+                for i in r..r + k {
+                    let bit = if i < state_size {
+                        state[i as usize / 8] >> (i % 8)
+                    } else {
+                        // The second state is read with the most significant bit first
+                        let i = i - state_size;
+                        state[1 + i as usize / 8] >> (7 - i % 8)
+                    };
+                    fx = (fx << 1) | (bit & 1) as u64;
+                }
+                (fx << PARAM_EXT) | (x >> (k - PARAM_EXT as u64))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bits::from_bits;
+
+    #[test]
+    fn test_calculate_f1_range() {
+        for k in [12, 14, 17, 25] {
+            let f1 = F1Calculator::new(k, *b"aaaabbbbccccddddaaaabbbbccccdddd");
+            for (start, count) in [(0, 1000), (1 << (k - 1), 517), ((1 << k) - 100, 100)] {
+                let expected = (start..start + count)
+                    .map(|x| from_bits(&f1.calculate_f1(&BitsWrapper::from(x, k)).unwrap()))
+                    .collect::<Vec<u64>>();
+                assert_eq!(f1.calculate_f1_range(start, count), expected);
+            }
+        }
+        assert!(F1Calculator::new(12, [0; 32])
+            .calculate_f1_range(5, 0)
+            .is_empty());
+    }
 }
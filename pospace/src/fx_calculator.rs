@@ -1,9 +1,18 @@
-use bitvec::prelude::*;
-
+use crate::bits::{from_bits, from_bits_u128, to_bits, to_bits_u128};
 use crate::constants::{PARAM_B, PARAM_BC, PARAM_C, PARAM_M};
 use crate::core::collation_size_bits;
 use crate::storage::PlotEntry;
 use crate::{constants::PARAM_EXT, Bits, BitsSlice};
+use std::sync::OnceLock;
+
+/// Size in bytes of the input of the hash of `calculate_fn`, y and two metadata
+/// of at most 128 bits
+const FN_INPUT_SIZE: usize = (64 + 2 * 128) / 8;
+
+type LeftTargets = Vec<Vec<Vec<u64>>>;
+
+/// The targets only depend on the parameters, they are shared by all the calculators
+static LEFT_TARGETS: OnceLock<LeftTargets> = OnceLock::new();
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 struct RmapItem {
@@ -16,67 +25,105 @@ pub struct FxCalculator {
     k: usize,
     table_index: usize,
     f_size: usize,
-    left_targets: Vec<Vec<Vec<u64>>>,
+    left_targets: &'static LeftTargets,
     rmap: Vec<RmapItem>,
     rmap_clean: Vec<u64>,
 }
 
 impl FxCalculator {
     pub fn new(k: usize, table_index: usize) -> Self {
-        let mut fx = FxCalculator {
+        FxCalculator {
             k,
             table_index,
             f_size: k + PARAM_EXT,
-            left_targets: vec![vec![vec![0; PARAM_M as usize]; PARAM_BC as usize]; 2],
+            left_targets: LEFT_TARGETS.get_or_init(load_left_targets),
             rmap: vec![RmapItem { count: 0, pos: 0 }; PARAM_BC as usize],
             rmap_clean: vec![],
-        };
-        fx.load_tables();
-        fx
+        }
     }
 
+    /// Bit-oriented version of `calculate_fn_int`, y1 and the metadata are given
+    /// with the most significant bit first
     pub fn calculate_fn(
         &self,
         y1: &BitsSlice,
         left: &BitsSlice,
         right: &BitsSlice,
     ) -> (Bits, Bits) {
-        let mut input = Bits::new();
-        let mut c = Bits::new();
-        let mut hasher = blake3::Hasher::new();
-
-        if self.table_index < 4 {
-            c.extend_from_bitslice(left);
-            c.extend_from_bitslice(right);
-        }
-
-        input.extend_from_bitslice(y1);
-        input.extend_from_bitslice(left);
-        input.extend_from_bitslice(right);
+        let (y, c) = self.calculate(
+            from_bits(y1),
+            from_bits_u128(left),
+            from_bits_u128(right),
+            left.len(),
+        );
+        (
+            to_bits(y, self.f_size),
+            to_bits_u128(c, self.output_metadata_size(left.len())),
+        )
+    }
 
-        hasher.update(input.as_raw_slice());
+    /// Computes the fx and the metadata of an entry of the table from y1 and the
+    /// metadata of the two matching entries of the previous table
+    ///
+    /// The metadata holds `collation_size_bits(table_index, k)` bits, at most 128.
+    pub fn calculate_fn_int(&self, y1: u64, left: u128, right: u128) -> (u64, u128) {
+        self.calculate(
+            y1,
+            left,
+            right,
+            collation_size_bits(self.table_index, self.k),
+        )
+    }
 
-        let hash = hasher.finalize().as_bytes().view_bits::<Lsb0>().to_bitvec();
-        let output = hash[0..(self.k + PARAM_EXT)].to_bitvec();
+    fn calculate(&self, y1: u64, left: u128, right: u128, metadata_size: usize) -> (u64, u128) {
+        debug_assert!(metadata_size <= 128);
+
+        // Same layout as the raw bytes of the bit vector y1 || left || right
+        let mut input = [0u8; FN_INPUT_SIZE];
+        write_bits(&mut input, 0, y1 as u128, self.f_size);
+        write_bits(&mut input, self.f_size, left, metadata_size);
+        write_bits(
+            &mut input,
+            self.f_size + metadata_size,
+            right,
+            metadata_size,
+        );
+        let input_size = (self.f_size + 2 * metadata_size).div_ceil(8);
+        let hash = blake3::hash(&input[..input_size]);
+
+        // The bits of the hash, the first one being the most significant
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&hash.as_bytes()[..16]);
+        let hash_bits = u128::from_le_bytes(bytes).reverse_bits();
+
+        let y = (hash_bits >> (128 - self.f_size)) as u64;
+        let c = match self.table_index {
+            0..=3 => (left << metadata_size) | right,
+            4..=6 => hash_bits >> (128 - self.output_metadata_size(metadata_size)),
+            _ => 0,
+        };
+        (y, c)
+    }
 
-        if self.table_index >= 4 && self.table_index < 7 {
-            c = hash[0..collation_size_bits(self.table_index + 1, self.k)].to_bitvec();
+    fn output_metadata_size(&self, metadata_size: usize) -> usize {
+        match self.table_index {
+            0..=3 => 2 * metadata_size,
+            4..=6 => collation_size_bits(self.table_index + 1, self.k),
+            _ => 0,
         }
-
-        return (output, c);
     }
 
-    fn load_tables(&mut self) {
-        for parity in 0..2 {
-            for i in 0..PARAM_BC {
-                let ind_j = i / PARAM_C;
-                for m in 0..PARAM_M {
-                    let yr = ((ind_j + m) % PARAM_B) * PARAM_C
-                        + (((2 * m + parity) * (2 * m + parity) + i) % PARAM_C);
-                    self.left_targets[parity as usize][i as usize][m as usize] = yr;
-                }
-            }
+    /// Whether the two f-values match, the left one being in the bucket before
+    /// the right one
+    ///
+    /// Unlike `find_matches`, it can be shared between threads.
+    pub fn is_match(&self, left_fx: u64, right_fx: u64) -> bool {
+        let left_bucket = left_fx / PARAM_BC;
+        if left_bucket + 1 != right_fx / PARAM_BC {
+            return false;
         }
+        let targets = &self.left_targets[(left_bucket % 2) as usize][(left_fx % PARAM_BC) as usize];
+        targets.contains(&(right_fx % PARAM_BC))
     }
 
     pub fn find_matches(
@@ -122,6 +169,40 @@ impl FxCalculator {
     }
 }
 
+/// Right f-values matching a left f-value, by parity of the left bucket and
+/// position of the left f-value in its bucket
+fn load_left_targets() -> LeftTargets {
+    let mut left_targets = vec![vec![vec![0; PARAM_M as usize]; PARAM_BC as usize]; 2];
+    for parity in 0..2 {
+        for i in 0..PARAM_BC {
+            let ind_j = i / PARAM_C;
+            for m in 0..PARAM_M {
+                let yr = ((ind_j + m) % PARAM_B) * PARAM_C
+                    + (((2 * m + parity) * (2 * m + parity) + i) % PARAM_C);
+                left_targets[parity as usize][i as usize][m as usize] = yr;
+            }
+        }
+    }
+    left_targets
+}
+
+/// Writes the `size` lowest bits of `value` at bit `offset` with the most
+/// significant bit first, in the layout of the raw bytes of a bit vector
+fn write_bits(buffer: &mut [u8], offset: usize, value: u128, size: usize) {
+    if size == 0 {
+        return;
+    }
+    let mut value = value.reverse_bits() >> (128 - size);
+    let mut position = offset;
+    while position < offset + size {
+        let shift = position % 8;
+        let amount = (8 - shift).min(offset + size - position);
+        buffer[position / 8] |= ((value & ((1 << amount) - 1)) << shift) as u8;
+        value >>= amount;
+        position += amount;
+    }
+}
+
 #[derive(Debug)]
 pub struct Match {
     pub left_index: usize,
@@ -160,10 +241,73 @@ pub fn matching_naive(l: u64, r: u64) -> bool {
 mod tests {
 
     use super::*;
-    use crate::bits::{from_bits, to_bits, BitsWrapper};
+    use crate::bits::BitsWrapper;
     use crate::f1_calculator::F1Calculator;
+    use bitvec::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::collections::BTreeMap;
 
+    /// Bit vector implementation of calculate_fn used before the integer one
+    fn calculate_fn_reference(
+        fx: &FxCalculator,
+        y1: &BitsSlice,
+        left: &BitsSlice,
+        right: &BitsSlice,
+    ) -> (Bits, Bits) {
+        let mut input = Bits::new();
+        let mut c = Bits::new();
+        let mut hasher = blake3::Hasher::new();
+
+        if fx.table_index < 4 {
+            c.extend_from_bitslice(left);
+            c.extend_from_bitslice(right);
+        }
+
+        input.extend_from_bitslice(y1);
+        input.extend_from_bitslice(left);
+        input.extend_from_bitslice(right);
+
+        hasher.update(input.as_raw_slice());
+
+        let hash = hasher.finalize().as_bytes().view_bits::<Lsb0>().to_bitvec();
+        let output = hash[0..(fx.k + PARAM_EXT)].to_bitvec();
+
+        if fx.table_index >= 4 && fx.table_index < 7 {
+            c = hash[0..collation_size_bits(fx.table_index + 1, fx.k)].to_bitvec();
+        }
+
+        (output, c)
+    }
+
+    #[test]
+    fn test_calculate_fn_int() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for k in [12, 14, 23, 32] {
+            for table_index in 2..=7 {
+                let fx = FxCalculator::new(k, table_index);
+                let metadata_size = collation_size_bits(table_index, k);
+                for _ in 0..50 {
+                    let y1 = rng.gen::<u64>() >> (64 - (k + PARAM_EXT));
+                    let left = rng.gen::<u128>() >> (128 - metadata_size);
+                    let right = rng.gen::<u128>() >> (128 - metadata_size);
+                    let (y1_bits, left_bits, right_bits) = (
+                        to_bits(y1, k + PARAM_EXT),
+                        to_bits_u128(left, metadata_size),
+                        to_bits_u128(right, metadata_size),
+                    );
+
+                    let expected = calculate_fn_reference(&fx, &y1_bits, &left_bits, &right_bits);
+                    assert_eq!(fx.calculate_fn(&y1_bits, &left_bits, &right_bits), expected);
+
+                    let (y, c) = fx.calculate_fn_int(y1, left, right);
+                    assert_eq!(y, from_bits(&expected.0));
+                    assert_eq!(c, from_bits_u128(&expected.1));
+                }
+            }
+        }
+    }
+
     fn verify_fc(t: usize, k: usize, left: u64, right: u64, y1: u64, y: u64, c: Option<u64>) {
         let sizes = [1, 2, 4, 4, 3, 2];
         let size = sizes[(t - 2) as usize];
@@ -298,6 +442,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_is_match() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let fx = FxCalculator::new(12, 2);
+        let mut matches = 0;
+        for _ in 0..100_000 {
+            let left = rng.gen_range(0..1u64 << 18);
+            let right = left - left % PARAM_BC + rng.gen_range(0..3 * PARAM_BC);
+            assert_eq!(fx.is_match(left, right), matching_naive(left, right));
+            matches += fx.is_match(left, right) as usize;
+        }
+        assert!(matches > 0);
+    }
+
     #[test]
     fn test_fx() {
         verify_fc(2, 16, 0x44cb, 0x204f, 0x20a61a, 0x39274C, Some(0x44CB204F));
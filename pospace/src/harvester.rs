@@ -0,0 +1,228 @@
+use crate::constants::PARAM_PLOT_FILTER;
+use crate::core::PoSpace;
+use crate::error::HarvesterError;
+use crate::plot::{PlotHeader, PLOT_EXTENSION};
+use crate::proofs::{passes_plot_filter, Proof, Prover, QualityString};
+use anyhow::{Context, Result};
+use log::*;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Default number of plots of the same disk read at the same time
+pub const DEFAULT_DISK_CONCURRENCY: usize = 2;
+
+/// Plot known by the harvester
+#[derive(Debug, Clone)]
+pub struct HarvestedPlot {
+    pub header: PlotHeader,
+    /// Directory in which the plot was found, the plots of a directory are
+    /// considered to be on the same disk
+    pub directory: PathBuf,
+    pospace: PoSpace,
+}
+
+impl HarvestedPlot {
+    pub fn path(&self) -> &Path {
+        self.pospace.plot_path()
+    }
+}
+
+/// Quality of a proof found in one of the plots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlotQuality {
+    pub plot_path: PathBuf,
+    /// Index of the proof among the proofs of the plot for the challenge
+    pub index: usize,
+    pub quality: QualityString,
+}
+
+/// Serves the challenges with every plot found in a list of directories
+///
+/// The registry is only updated by `refresh`, which picks up the plots added
+/// or removed since the last call.
+pub struct Harvester {
+    directories: RwLock<Vec<PathBuf>>,
+    plots: RwLock<BTreeMap<PathBuf, HarvestedPlot>>,
+    disk_concurrency: usize,
+}
+
+impl Harvester {
+    pub fn new(directories: &[PathBuf]) -> Self {
+        Harvester {
+            directories: RwLock::new(directories.to_vec()),
+            plots: RwLock::new(BTreeMap::new()),
+            disk_concurrency: DEFAULT_DISK_CONCURRENCY,
+        }
+    }
+
+    /// Limits the number of plots of the same disk read at the same time
+    pub fn with_disk_concurrency(mut self, disk_concurrency: usize) -> Self {
+        self.disk_concurrency = disk_concurrency.max(1);
+        self
+    }
+
+    /// The plots of the directory are added by the next refresh
+    pub fn add_directory(&self, directory: &Path) {
+        let mut directories = self.directories.write().unwrap();
+        if !directories.iter().any(|d| d == directory) {
+            directories.push(directory.to_owned());
+        }
+    }
+
+    /// The plots of the directory are removed by the next refresh
+    pub fn remove_directory(&self, directory: &Path) {
+        self.directories.write().unwrap().retain(|d| d != directory);
+    }
+
+    /// Scans the directories, opens the new plots and forgets the plots that
+    /// no longer exist, returns the number of plots added and removed
+    pub fn refresh(&self) -> Result<(usize, usize)> {
+        let directories = self.directories.read().unwrap().clone();
+        let mut found = BTreeMap::new();
+        for directory in &directories {
+            match list_plots(directory) {
+                Ok(paths) => found.extend(paths.into_iter().map(|p| (p, directory.clone()))),
+                Err(e) => warn!("Could not scan the directory {:?}: {:#}", directory, e),
+            }
+        }
+
+        let known = self
+            .plots
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<BTreeSet<PathBuf>>();
+
+        // Headers are read without holding the lock, lookups can continue meanwhile
+        let new_plots = found
+            .iter()
+            .filter(|(path, _)| !known.contains(*path))
+            .filter_map(|(path, directory)| match open_plot(path, directory) {
+                Ok(plot) => Some(plot),
+                Err(e) => {
+                    warn!("Could not open the plot {:?}: {:#}", path, e);
+                    None
+                }
+            })
+            .collect::<Vec<HarvestedPlot>>();
+
+        let mut plots = self.plots.write().unwrap();
+        let count = plots.len();
+        plots.retain(|path, _| found.contains_key(path));
+        let removed = count - plots.len();
+        let added = new_plots.len();
+        for plot in new_plots {
+            plots.insert(plot.path().to_owned(), plot);
+        }
+
+        if added > 0 || removed > 0 {
+            info!(
+                "{} plots added and {} removed, {} plots harvested",
+                added,
+                removed,
+                plots.len()
+            );
+        }
+        Ok((added, removed))
+    }
+
+    /// Plots of the registry, sorted by path
+    pub fn plots(&self) -> Vec<HarvestedPlot> {
+        self.plots.read().unwrap().values().cloned().collect()
+    }
+
+    /// Quality strings of the proofs of every plot for the challenge
+    ///
+    /// Only the plots passing the plot filter are read, in parallel with at most
+    /// `disk_concurrency` plots of the same disk at a time. A plot that cannot
+    /// be read is skipped.
+    pub fn get_qualities(&self, challenge: &[u8], signage_data: &[u8]) -> Vec<PlotQuality> {
+        let mut disks = BTreeMap::<PathBuf, Vec<Vec<HarvestedPlot>>>::new();
+        let plots = self.plots().into_iter().filter(|plot| {
+            passes_plot_filter(
+                &plot.header.plot_id,
+                challenge,
+                signage_data,
+                PARAM_PLOT_FILTER,
+            )
+        });
+        for plot in plots {
+            let lanes = disks
+                .entry(plot.directory.clone())
+                .or_insert_with(|| vec![Vec::new(); self.disk_concurrency]);
+            let lane = lanes.iter_mut().min_by_key(|lane| lane.len()).unwrap();
+            lane.push(plot);
+        }
+
+        // Each lane reads its plots one after the other
+        let lanes = disks.into_values().flatten().collect::<Vec<_>>();
+        let mut qualities = lanes
+            .into_par_iter()
+            .flat_map_iter(|lane| {
+                lane.into_iter()
+                    .flat_map(|plot| plot_qualities(&plot, challenge, signage_data))
+            })
+            .collect::<Vec<PlotQuality>>();
+        qualities.sort_by(|a, b| (&a.plot_path, a.index).cmp(&(&b.plot_path, b.index)));
+        qualities
+    }
+
+    /// Full proof of a quality returned by `get_qualities`
+    pub fn get_proof(
+        &self,
+        plot_path: &Path,
+        challenge: &[u8],
+        signage_data: &[u8],
+        index: usize,
+    ) -> Result<Proof> {
+        let pospace = self
+            .plots
+            .read()
+            .unwrap()
+            .get(plot_path)
+            .map(|plot| plot.pospace.clone())
+            .ok_or_else(|| HarvesterError::UnknownPlot(plot_path.to_owned()))?;
+        Prover::new(pospace)
+            .get_full_proof(challenge, signage_data, index)
+            .context(format!("Could not retrieve the proof of {:?}", plot_path))?
+            .ok_or_else(|| HarvesterError::ProofNotFound(index).into())
+    }
+}
+
+fn list_plots(directory: &Path) -> Result<Vec<PathBuf>> {
+    Ok(read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == PLOT_EXTENSION))
+        .collect())
+}
+
+fn open_plot(path: &Path, directory: &Path) -> Result<HarvestedPlot> {
+    let pospace = PoSpace::open(path)?;
+    Ok(HarvestedPlot {
+        header: pospace.plot_header()?.clone(),
+        directory: directory.to_owned(),
+        pospace,
+    })
+}
+
+fn plot_qualities(plot: &HarvestedPlot, challenge: &[u8], signage_data: &[u8]) -> Vec<PlotQuality> {
+    match Prover::new(plot.pospace.clone()).get_quality_string(challenge, signage_data) {
+        Ok(qualities) => qualities
+            .into_iter()
+            .enumerate()
+            .map(|(index, quality)| PlotQuality {
+                plot_path: plot.path().to_owned(),
+                index,
+                quality,
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Could not read the plot {:?}: {:#}", plot.path(), e);
+            Vec::new()
+        }
+    }
+}
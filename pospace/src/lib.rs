@@ -1,11 +1,21 @@
 use bitvec::prelude::*;
 
+pub mod backprop;
+pub mod bitfield;
 pub mod bits;
+pub mod check;
+pub mod checkpoint;
+pub mod compression;
+pub mod config;
 pub mod constants;
 pub mod core;
 pub mod error;
 pub mod f1_calculator;
 pub mod fx_calculator;
+pub mod harvester;
+pub mod plot;
+pub mod plot_id;
+pub mod progress;
 pub mod proofs;
 pub mod sort;
 pub mod storage;
@@ -14,6 +24,3 @@ pub mod verifier;
 
 pub type Bits = BitVec<Lsb0, u8>;
 pub type BitsSlice = BitSlice<Lsb0, u8>;
-
-#[macro_use]
-extern crate lazy_static;
@@ -0,0 +1,677 @@
+use crate::constants::{MAX_K, MIN_K};
+use crate::core::{PlotSeed, NUMBER_OF_TABLES};
+use crate::error::{PlotError, StorageError};
+use crate::plot_id::PlotId;
+use crate::storage::{compressed_entry_size, deserialize_compressed, CompressedEntry};
+use crate::table_final_filename_format;
+use anyhow::{Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
+use std::convert::TryInto;
+use std::fmt::Debug;
+use std::fs::{remove_file, File};
+use std::io::{self, copy, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const PLOT_MAGIC: [u8; 16] = *b"spaceframe-plot\0";
+pub const PLOT_FORMAT_VERSION: u16 = 3;
+pub const PLOT_EXTENSION: &str = "plot";
+/// Number of table 7 entries between two entries of the index
+pub const INDEX_INTERVAL: u64 = 4096;
+/// Size in bytes of an entry of the index
+const INDEX_ENTRY_SIZE: u64 = 8;
+
+/// Header written at the beginning of every plot file
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlotHeader {
+    pub magic: [u8; 16],
+    pub version: u16,
+    pub k: u8,
+    pub plot_id: PlotId,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    /// Position in bytes of each table from the beginning of the file
+    pub table_offsets: [u64; NUMBER_OF_TABLES],
+    /// Size in bytes of each table
+    pub table_sizes: [u64; NUMBER_OF_TABLES],
+    /// Position in bytes of the index of table 7
+    pub index_offset: u64,
+    /// Size in bytes of the index of table 7
+    pub index_size: u64,
+}
+
+impl PlotHeader {
+    pub fn new(k: usize, plot_id: PlotId) -> Self {
+        PlotHeader {
+            magic: PLOT_MAGIC,
+            version: PLOT_FORMAT_VERSION,
+            k: k as u8,
+            plot_id,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            table_offsets: [0; NUMBER_OF_TABLES],
+            table_sizes: [0; NUMBER_OF_TABLES],
+            index_offset: 0,
+            index_size: 0,
+        }
+    }
+
+    /// Size in bytes of the serialized header
+    pub fn size() -> usize {
+        let public_key = Ed25519PrivateKey::from_bytes(&[0; 32])
+            .unwrap()
+            .public_key();
+        PlotHeader::new(0, PlotId::new(public_key, public_key, [0; 32]))
+            .try_to_vec()
+            .unwrap()
+            .len()
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut buffer = vec![0u8; PlotHeader::size()];
+        reader
+            .read_exact(&mut buffer)
+            .context("Could not read plot header")?;
+
+        if buffer[..PLOT_MAGIC.len()] != PLOT_MAGIC {
+            return Err(PlotError::InvalidMagic.into());
+        }
+
+        let header = PlotHeader::try_from_slice(&buffer).or(Err(PlotError::InvalidHeader))?;
+
+        if header.version != PLOT_FORMAT_VERSION {
+            return Err(PlotError::UnsupportedVersion(header.version).into());
+        }
+
+        if !(MIN_K..=MAX_K).contains(&(header.k as usize)) {
+            return Err(PlotError::InvalidHeader.into());
+        }
+
+        Ok(header)
+    }
+
+    /// Number of entries in the given table
+    pub fn entries_count(&self, table_index: usize) -> u64 {
+        self.table_sizes[table_index - 1]
+            / compressed_entry_size(table_index, self.k as usize) as u64
+    }
+
+    /// Number of entries in the index of table 7
+    pub fn index_entries_count(&self) -> u64 {
+        self.entries_count(NUMBER_OF_TABLES)
+            .div_ceil(INDEX_INTERVAL)
+    }
+}
+
+/// Reads the compressed table 7 and keeps the fx of every `INDEX_INTERVAL`th entry
+fn build_index(table7_path: &Path, k: usize) -> Result<Vec<u8>> {
+    let entry_size = compressed_entry_size(NUMBER_OF_TABLES, k);
+    let mut table7 =
+        File::open(table7_path).context(format!("Could not open table file {:?}", table7_path))?;
+    let mut buffer = vec![0u8; entry_size];
+    let mut index = Vec::new();
+    let mut position = 0;
+
+    loop {
+        match table7.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e).context(format!("Could not read {:?}", table7_path)),
+        }
+        let entry = deserialize_compressed(&buffer, NUMBER_OF_TABLES, k)?[0];
+        let fx = entry.fx.ok_or(StorageError::DeserializationError)?;
+        index.extend_from_slice(&fx.to_le_bytes());
+        position += INDEX_INTERVAL;
+        table7
+            .seek(SeekFrom::Start(position * entry_size as u64))
+            .context(format!("Could not seek in {:?}", table7_path))?;
+    }
+
+    Ok(index)
+}
+
+/// Default file name of a plot, deterministic for a given k and plot seed
+pub fn plot_filename(k: usize, plot_seed: &PlotSeed) -> String {
+    format!(
+        "plot-k{}-{}.{}",
+        k,
+        plot_seed
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>(),
+        PLOT_EXTENSION
+    )
+}
+
+/// Reads parts of a plot at any offset
+///
+/// The reads do not move a cursor, so a single reader is shared by all the
+/// challenges looked up in the plot at the same time. A reader could keep the
+/// parts read the most, like the index of table 7, in memory.
+pub trait PlotReader: Debug + Send + Sync {
+    /// Fills the buffer with the bytes of the plot starting at `offset`
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Size in bytes of the plot
+    fn size(&self) -> io::Result<u64>;
+}
+
+/// Reads the plot file directly with positioned reads
+#[derive(Debug)]
+pub struct FileReader {
+    file: File,
+}
+
+impl FileReader {
+    pub fn new(file: File) -> Self {
+        FileReader { file }
+    }
+}
+
+impl PlotReader for FileReader {
+    #[cfg(unix)]
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+        self.file.read_exact_at(buffer, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+        let mut read = 0;
+        while read < buffer.len() {
+            match self
+                .file
+                .seek_read(&mut buffer[read..], offset + read as u64)?
+            {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+}
+
+/// Reader over the bytes of a table, read from a `PlotReader`
+pub struct TableReader<'a> {
+    reader: &'a dyn PlotReader,
+    position: u64,
+    end: u64,
+}
+
+impl Read for TableReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let size = buffer.len().min((self.end - self.position) as usize);
+        self.reader.read_at(&mut buffer[..size], self.position)?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+/// Single file plot, containing the header and the seven compressed tables
+///
+/// The reads only borrow the plot, it can be cloned to share its reader.
+#[derive(Debug, Clone)]
+pub struct PlotFile {
+    pub header: PlotHeader,
+    pub path: PathBuf,
+    reader: Arc<dyn PlotReader>,
+}
+
+impl PlotFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!("Could not open plot {:?}", path))?;
+        PlotFile::with_reader(path, Arc::new(FileReader::new(file)))
+    }
+
+    /// Opens the plot whose content is given by the reader, `path` is only used
+    /// to name the plot
+    pub fn with_reader(path: &Path, reader: Arc<dyn PlotReader>) -> Result<Self> {
+        let mut buffer = vec![0u8; PlotHeader::size()];
+        let header = reader
+            .read_at(&mut buffer, 0)
+            .context("Could not read plot header")
+            .and_then(|_| PlotHeader::read(&mut buffer.as_slice()))
+            .context(format!("Invalid plot header in {:?}", path))?;
+        let file_size = reader.size()?;
+
+        for table_index in 1..=NUMBER_OF_TABLES {
+            let size = header.table_sizes[table_index - 1];
+            let end = header.table_offsets[table_index - 1].checked_add(size);
+            let entry_size = compressed_entry_size(table_index, header.k as usize) as u64;
+            if end.is_none_or(|end| end > file_size) || !size.is_multiple_of(entry_size) {
+                return Err(PlotError::InvalidTable(table_index).into());
+            }
+        }
+
+        let index_end = header.index_offset.checked_add(header.index_size);
+        if index_end.is_none_or(|end| end > file_size)
+            || header.index_size != header.index_entries_count() * INDEX_ENTRY_SIZE
+        {
+            return Err(PlotError::InvalidIndex.into());
+        }
+
+        Ok(PlotFile {
+            header,
+            path: path.to_owned(),
+            reader,
+        })
+    }
+
+    /// Writes the plot file from the seven compressed tables found in `tables_path`,
+    /// followed by the index of table 7, the table files are removed once copied
+    pub fn create(path: &Path, k: usize, plot_id: PlotId, tables_path: &Path) -> Result<Self> {
+        let mut header = PlotHeader::new(k, plot_id);
+        let table_paths = (1..=NUMBER_OF_TABLES)
+            .map(|i| tables_path.join(format!(table_final_filename_format!(), i)))
+            .collect::<Vec<PathBuf>>();
+
+        let mut offset = PlotHeader::size() as u64;
+        for (i, table_path) in table_paths.iter().enumerate() {
+            let size = table_path
+                .metadata()
+                .context(format!("Could not read size of {:?}", table_path))?
+                .len();
+            header.table_offsets[i] = offset;
+            header.table_sizes[i] = size;
+            offset += size;
+        }
+
+        let index = build_index(&table_paths[NUMBER_OF_TABLES - 1], k)
+            .context("Could not build the index of table 7")?;
+        header.index_offset = offset;
+        header.index_size = index.len() as u64;
+
+        let mut output = BufWriter::new(
+            File::create(path).context(format!("Could not create plot file {:?}", path))?,
+        );
+        output
+            .write_all(&header.try_to_vec()?)
+            .context("Could not write plot header")?;
+        for table_path in &table_paths {
+            let mut table = File::open(table_path)
+                .context(format!("Could not open table file {:?}", table_path))?;
+            copy(&mut table, &mut output)
+                .context(format!("Could not copy {:?} to the plot file", table_path))?;
+        }
+        output
+            .write_all(&index)
+            .context("Could not write the index of table 7")?;
+        output.flush().context("Could not write plot file")?;
+        drop(output);
+
+        for table_path in &table_paths {
+            remove_file(table_path).context(format!("Could not remove {:?}", table_path))?;
+        }
+
+        PlotFile::open(path)
+    }
+
+    /// Checks that the tables and the index follow each other without gaps and
+    /// fill the whole file, and that every table holds complete entries
+    pub fn validate_layout(&self) -> Result<()> {
+        let k = self.header.k as usize;
+        let mut offset = PlotHeader::size() as u64;
+        for table_index in 1..=NUMBER_OF_TABLES {
+            let size = self.header.table_sizes[table_index - 1];
+            if self.header.table_offsets[table_index - 1] != offset
+                || size == 0
+                || !size.is_multiple_of(compressed_entry_size(table_index, k) as u64)
+            {
+                return Err(PlotError::InvalidTable(table_index).into());
+            }
+            offset += size;
+        }
+        if self.header.index_offset != offset {
+            return Err(PlotError::InvalidIndex.into());
+        }
+
+        let expected = offset + self.header.index_size;
+        let file_size = self.reader.size()?;
+        if file_size != expected {
+            return Err(PlotError::InvalidFileSize(file_size, expected).into());
+        }
+        Ok(())
+    }
+
+    /// Reads the entry at the given position in a table
+    pub fn read_entry(&self, table_index: usize, position: u64) -> Result<CompressedEntry> {
+        if position >= self.header.entries_count(table_index) {
+            return Err(PlotError::PositionOutOfTable(position, table_index).into());
+        }
+        let k = self.header.k as usize;
+        let entry_size = compressed_entry_size(table_index, k);
+        let mut buffer = vec![0u8; entry_size];
+        self.reader
+            .read_at(
+                &mut buffer,
+                self.header.table_offsets[table_index - 1] + position * entry_size as u64,
+            )
+            .context(format!(
+                "Could not read entry at position {} in table {}",
+                position, table_index
+            ))?;
+        Ok(deserialize_compressed(&buffer, table_index, k)?[0])
+    }
+
+    /// Reads the first k bits of fx of every `INDEX_INTERVAL`th entry of table 7
+    pub fn read_index(&self) -> Result<Vec<u64>> {
+        let mut buffer = vec![0u8; self.header.index_size as usize];
+        self.reader
+            .read_at(&mut buffer, self.header.index_offset)
+            .context("Could not read the index of table 7")?;
+        Ok(buffer
+            .chunks_exact(INDEX_ENTRY_SIZE as usize)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Entries of table 7 whose first k bits of fx are equal to `fx`
+    ///
+    /// Table 7 is sorted by fx, the index gives the part of the table where the
+    /// entries are, which is usually read at once.
+    pub fn find_table7_entries(&self, index: &[u64], fx: u64) -> Result<Vec<CompressedEntry>> {
+        let k = self.header.k as usize;
+        let entries_count = self.header.entries_count(NUMBER_OF_TABLES);
+        let entry_size = compressed_entry_size(NUMBER_OF_TABLES, k) as u64;
+        let table_offset = self.header.table_offsets[NUMBER_OF_TABLES - 1];
+
+        // Last indexed entry smaller than fx, the entries before it are all smaller
+        let mut position = index
+            .partition_point(|&indexed| indexed < fx)
+            .saturating_sub(1) as u64
+            * INDEX_INTERVAL;
+        let mut entries = Vec::new();
+
+        while position < entries_count {
+            let count = INDEX_INTERVAL.min(entries_count - position);
+            let mut buffer = vec![0u8; (count * entry_size) as usize];
+            self.reader
+                .read_at(&mut buffer, table_offset + position * entry_size)
+                .context("Could not read table 7")?;
+
+            let chunk = deserialize_compressed(&buffer, NUMBER_OF_TABLES, k)?;
+            let is_last_chunk = chunk.last().is_some_and(|entry| entry.fx > Some(fx));
+            entries.extend(chunk.into_iter().filter(|entry| entry.fx == Some(fx)));
+            if is_last_chunk {
+                break;
+            }
+            position += count;
+        }
+
+        Ok(entries)
+    }
+
+    /// Reader over the whole content of a table
+    pub fn table_reader(&self, table_index: usize) -> TableReader<'_> {
+        let position = self.header.table_offsets[table_index - 1];
+        TableReader {
+            reader: self.reader.as_ref(),
+            position,
+            end: position + self.header.table_sizes[table_index - 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::serialize_compressed;
+    use tempdir::TempDir;
+
+    fn test_plot_id() -> PlotId {
+        let public_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+            .unwrap()
+            .public_key();
+        PlotId::new(public_key, public_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+    }
+
+    fn write_tables(path: &Path, k: usize) {
+        for table_index in 1..=NUMBER_OF_TABLES {
+            let entries = (0..table_index as u64 * 3)
+                .map(|i| CompressedEntry {
+                    x: Some(i),
+                    fx: Some(i),
+                    line_point: Some(i as u128 * 1000),
+                })
+                .collect::<Vec<CompressedEntry>>();
+            let mut file =
+                File::create(path.join(format!(table_final_filename_format!(), table_index)))
+                    .unwrap();
+            file.write_all(&serialize_compressed(&entries, table_index, k).unwrap())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = PlotHeader::new(18, test_plot_id());
+        let bytes = header.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), PlotHeader::size());
+        assert_eq!(PlotHeader::read(&mut bytes.as_slice()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_header_invalid_magic() {
+        let mut bytes = PlotHeader::new(18, test_plot_id()).try_to_vec().unwrap();
+        bytes[0] = b'x';
+        let err = PlotHeader::read(&mut bytes.as_slice()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PlotError>(),
+            Some(PlotError::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn test_header_unsupported_version() {
+        let mut header = PlotHeader::new(18, test_plot_id());
+        header.version = PLOT_FORMAT_VERSION + 1;
+        let bytes = header.try_to_vec().unwrap();
+        let err = PlotHeader::read(&mut bytes.as_slice()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PlotError>(),
+            Some(PlotError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_invalid_k() {
+        let dir = TempDir::new("spaceframe_plot").unwrap();
+        for &k in &[0u8, 255] {
+            let mut header = PlotHeader::new(18, test_plot_id());
+            header.k = k;
+            let bytes = header.try_to_vec().unwrap();
+            let err = PlotHeader::read(&mut bytes.as_slice()).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<PlotError>(),
+                Some(PlotError::InvalidHeader)
+            ));
+
+            let path = dir.path().join(format!("k{}.plot", k));
+            File::create(&path).unwrap().write_all(&bytes).unwrap();
+            assert!(PlotFile::open(&path).is_err());
+        }
+    }
+
+    #[test]
+    fn test_create_and_read_plot() {
+        const TEST_K: usize = 12;
+        let dir = TempDir::new("spaceframe_plot").unwrap();
+        let plot_id = test_plot_id();
+        write_tables(dir.path(), TEST_K);
+
+        let path = dir.path().join(plot_filename(TEST_K, &plot_id.plot_seed()));
+        PlotFile::create(&path, TEST_K, plot_id, dir.path()).unwrap();
+
+        for table_index in 1..=NUMBER_OF_TABLES {
+            assert!(!dir
+                .path()
+                .join(format!(table_final_filename_format!(), table_index))
+                .exists());
+        }
+
+        let plot_file = PlotFile::open(&path).unwrap();
+        assert_eq!(plot_file.header.k as usize, TEST_K);
+        assert_eq!(plot_file.header.plot_id, plot_id);
+        assert_eq!(plot_file.header.entries_count(4), 12);
+        assert_eq!(plot_file.read_entry(1, 2).unwrap().x, Some(2));
+        assert_eq!(plot_file.read_entry(5, 7).unwrap().line_point, Some(7000));
+        assert_eq!(plot_file.read_entry(7, 20).unwrap().fx, Some(20));
+        assert!(plot_file.read_entry(2, 6).is_err());
+    }
+
+    /// Plot kept in memory, as a reader with a cache would do
+    #[derive(Debug)]
+    struct MemoryReader(Vec<u8>);
+
+    impl PlotReader for MemoryReader {
+        fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+            let start = offset as usize;
+            let bytes = self
+                .0
+                .get(start..start + buffer.len())
+                .ok_or(ErrorKind::UnexpectedEof)?;
+            buffer.copy_from_slice(bytes);
+            Ok(())
+        }
+
+        fn size(&self) -> io::Result<u64> {
+            Ok(self.0.len() as u64)
+        }
+    }
+
+    #[test]
+    fn test_plot_reader() {
+        const TEST_K: usize = 12;
+        let dir = TempDir::new("spaceframe_plot").unwrap();
+        write_tables(dir.path(), TEST_K);
+        let path = dir.path().join("memory.plot");
+        let plot_file = PlotFile::create(&path, TEST_K, test_plot_id(), dir.path()).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let memory_file =
+            PlotFile::with_reader(&path, Arc::new(MemoryReader(bytes.clone()))).unwrap();
+        assert_eq!(memory_file.header, plot_file.header);
+        assert!(memory_file.validate_layout().is_ok());
+        for (table_index, position) in [(1, 2), (5, 7), (7, 20)].iter() {
+            assert_eq!(
+                memory_file.read_entry(*table_index, *position).unwrap(),
+                plot_file.read_entry(*table_index, *position).unwrap()
+            );
+        }
+
+        // The clones share the reader, the reads of a table do not move a cursor
+        let clone = plot_file.clone();
+        let mut table = Vec::new();
+        plot_file.table_reader(5).read_to_end(&mut table).unwrap();
+        let start = plot_file.header.table_offsets[4] as usize;
+        assert_eq!(
+            table,
+            &bytes[start..start + plot_file.header.table_sizes[4] as usize]
+        );
+        assert_eq!(
+            clone.read_entry(1, 2).unwrap(),
+            plot_file.read_entry(1, 2).unwrap()
+        );
+
+        // A plot whose header cannot be read is rejected
+        assert!(
+            PlotFile::with_reader(&path, Arc::new(MemoryReader(bytes[..10].to_vec()))).is_err()
+        );
+    }
+
+    #[test]
+    fn test_open_truncated_plot() {
+        const TEST_K: usize = 12;
+        let dir = TempDir::new("spaceframe_plot").unwrap();
+        write_tables(dir.path(), TEST_K);
+
+        let path = dir.path().join("truncated.plot");
+        PlotFile::create(&path, TEST_K, test_plot_id(), dir.path()).unwrap();
+        let size = path.metadata().unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(size - 1)
+            .unwrap();
+
+        assert!(PlotFile::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_validate_layout() {
+        const TEST_K: usize = 12;
+        let dir = TempDir::new("spaceframe_plot").unwrap();
+        write_tables(dir.path(), TEST_K);
+
+        let path = dir.path().join("layout.plot");
+        PlotFile::create(&path, TEST_K, test_plot_id(), dir.path()).unwrap();
+        assert!(PlotFile::open(&path).unwrap().validate_layout().is_ok());
+
+        File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0])
+            .unwrap();
+        let err = PlotFile::open(&path)
+            .unwrap()
+            .validate_layout()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PlotError>(),
+            Some(PlotError::InvalidFileSize(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_find_table7_entries() {
+        const TEST_K: usize = 14;
+        let dir = TempDir::new("spaceframe_plot").unwrap();
+        write_tables(dir.path(), TEST_K);
+
+        // Sorted fx with runs of equal values crossing the index intervals
+        let table7 = (0..3 * INDEX_INTERVAL + 100)
+            .map(|i| CompressedEntry {
+                x: None,
+                fx: Some(i / 7 * 2),
+                line_point: Some(i as u128),
+            })
+            .collect::<Vec<CompressedEntry>>();
+        File::create(dir.path().join(format!(table_final_filename_format!(), 7)))
+            .unwrap()
+            .write_all(&serialize_compressed(&table7, 7, TEST_K).unwrap())
+            .unwrap();
+
+        let path = dir.path().join("indexed.plot");
+        let plot_file = PlotFile::create(&path, TEST_K, test_plot_id(), dir.path()).unwrap();
+        assert_eq!(plot_file.header.index_entries_count(), 4);
+        let index = plot_file.read_index().unwrap();
+        assert_eq!(index[1], INDEX_INTERVAL / 7 * 2);
+
+        for fx in [0, 1, 1170, 1172, INDEX_INTERVAL / 7 * 2, 3540, 3542, 5000].iter() {
+            let expected = table7
+                .iter()
+                .filter(|entry| entry.fx == Some(*fx))
+                .cloned()
+                .collect::<Vec<CompressedEntry>>();
+            assert_eq!(
+                plot_file.find_table7_entries(&index, *fx).unwrap(),
+                expected,
+                "Wrong entries for fx {}",
+                fx
+            );
+        }
+    }
+}
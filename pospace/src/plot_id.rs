@@ -0,0 +1,75 @@
+use crate::core::PlotSeed;
+use borsh::{BorshDeserialize, BorshSerialize};
+use spaceframe_crypto::ed25519::Ed25519PublicKey;
+use spaceframe_crypto::traits::PublicKey;
+
+/// Identity of a plot, the plot seed is derived from it
+///
+/// Binding the farmer and pool public keys to the plot seed means that a proof
+/// can only be claimed by the owners of these keys, the random nonce makes every
+/// plot different even with the same keys.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlotId {
+    pub farmer_public_key: Ed25519PublicKey,
+    pub pool_public_key: Ed25519PublicKey,
+    pub nonce: [u8; 32],
+}
+
+impl PlotId {
+    pub fn new(
+        farmer_public_key: Ed25519PublicKey,
+        pool_public_key: Ed25519PublicKey,
+        nonce: [u8; 32],
+    ) -> Self {
+        PlotId {
+            farmer_public_key,
+            pool_public_key,
+            nonce,
+        }
+    }
+
+    /// Hash of the public keys and of the nonce
+    pub fn plot_seed(&self) -> PlotSeed {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.farmer_public_key.as_bytes());
+        hasher.update(self.pool_public_key.as_bytes());
+        hasher.update(&self.nonce);
+        *hasher.finalize().as_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+    use spaceframe_crypto::traits::PrivateKey;
+
+    fn public_key(seed: u8) -> Ed25519PublicKey {
+        Ed25519PrivateKey::from_bytes(&[seed; 32])
+            .unwrap()
+            .public_key()
+    }
+
+    #[test]
+    fn test_plot_seed() {
+        let plot_id = PlotId::new(public_key(1), public_key(2), [3; 32]);
+        assert_eq!(plot_id.plot_seed(), plot_id.plot_seed());
+        assert_ne!(
+            plot_id.plot_seed(),
+            PlotId::new(public_key(2), public_key(1), [3; 32]).plot_seed()
+        );
+        assert_ne!(
+            plot_id.plot_seed(),
+            PlotId::new(public_key(1), public_key(2), [4; 32]).plot_seed()
+        );
+    }
+
+    #[test]
+    fn test_serialization() {
+        let plot_id = PlotId::new(public_key(1), public_key(2), [3; 32]);
+        let bytes = plot_id.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), 96);
+        assert_eq!(PlotId::try_from_slice(&bytes).unwrap(), plot_id);
+    }
+}
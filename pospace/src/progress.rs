@@ -0,0 +1,155 @@
+use crate::core::NUMBER_OF_TABLES;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Share of each phase in the whole plotting, in percent
+const PHASE_WEIGHTS: [f64; 4] = [50.0, 20.0, 20.0, 10.0];
+
+/// Receives the progress of a plot job, every method does nothing by default
+///
+/// The methods are called from the plotting thread, they must return quickly.
+pub trait ProgressSink: Send + Sync {
+    fn phase_started(&self, _phase: usize) {}
+
+    fn table_started(&self, _phase: usize, _table_index: usize) {}
+
+    fn table_finished(&self, _phase: usize, _table_index: usize) {}
+
+    /// Progress of the whole plotting in percent, with the size in bytes of the
+    /// files produced so far
+    fn progress(&self, _phase: usize, _percentage: f64, _bytes_written: u64) {}
+}
+
+/// Sink ignoring the progress
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// Stops a plot job when cancelled, the token can be cloned and cancelled from
+/// another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Forwards the events of the plotting to a sink and computes the overall progress
+#[derive(Clone)]
+pub(crate) struct ProgressTracker {
+    sink: Arc<dyn ProgressSink>,
+    bytes_written: Arc<AtomicU64>,
+}
+
+impl ProgressTracker {
+    pub fn new(sink: Arc<dyn ProgressSink>) -> Self {
+        ProgressTracker {
+            sink,
+            bytes_written: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn phase_started(&self, phase: usize) {
+        self.sink.phase_started(phase);
+    }
+
+    pub fn table_started(&self, phase: usize, table_index: usize) {
+        self.sink.table_started(phase, table_index);
+        self.table_progress(phase, table_index, 0.0);
+    }
+
+    /// `fraction` is the part of the table already processed, between 0 and 1
+    pub fn table_progress(&self, phase: usize, table_index: usize, fraction: f64) {
+        self.sink.progress(
+            phase,
+            percentage(phase, table_index, fraction),
+            self.bytes_written.load(Ordering::Relaxed),
+        );
+    }
+
+    /// `bytes` is the size of the files produced for the table
+    pub fn table_finished(&self, phase: usize, table_index: usize, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        self.sink.table_finished(phase, table_index);
+        self.table_progress(phase, table_index, 1.0);
+    }
+}
+
+impl fmt::Debug for ProgressTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressTracker")
+            .field("bytes_written", &self.bytes_written)
+            .finish()
+    }
+}
+
+/// Phase 1 computes tables 1 to 7, phase 2 goes back from table 7 to table 2,
+/// phase 3 compresses tables 1 to 6 and phase 4 table 7
+fn percentage(phase: usize, table_index: usize, fraction: f64) -> f64 {
+    let (step, steps) = match phase {
+        1 => (table_index - 1, NUMBER_OF_TABLES),
+        2 => (NUMBER_OF_TABLES - table_index, NUMBER_OF_TABLES - 1),
+        3 => (table_index - 1, NUMBER_OF_TABLES - 1),
+        _ => (0, 1),
+    };
+    let previous_phases = PHASE_WEIGHTS[..phase - 1].iter().sum::<f64>();
+    previous_phases
+        + PHASE_WEIGHTS[phase - 1] * (step as f64 + fraction.clamp(0.0, 1.0)) / steps as f64
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink(Mutex<Vec<f64>>);
+
+    impl ProgressSink for RecordingSink {
+        fn progress(&self, _phase: usize, percentage: f64, _bytes_written: u64) {
+            self.0.lock().unwrap().push(percentage);
+        }
+    }
+
+    #[test]
+    fn test_percentage() {
+        assert_eq!(percentage(1, 1, 0.0), 0.0);
+        assert_eq!(percentage(1, 7, 1.0), 50.0);
+        assert_eq!(percentage(2, 7, 0.0), 50.0);
+        assert_eq!(percentage(2, 2, 1.0), 70.0);
+        assert_eq!(percentage(3, 6, 1.0), 90.0);
+        assert_eq!(percentage(4, 7, 1.0), 100.0);
+    }
+
+    #[test]
+    fn test_tracker() {
+        let sink = Arc::new(RecordingSink::default());
+        let tracker = ProgressTracker::new(sink.clone());
+        tracker.table_started(4, 7);
+        tracker.table_progress(4, 7, 0.5);
+        tracker.table_finished(4, 7, 100);
+        assert_eq!(*sink.0.lock().unwrap(), vec![90.0, 95.0, 100.0]);
+        assert_eq!(tracker.bytes_written.load(Ordering::Relaxed), 100);
+    }
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let other = token.clone();
+        assert!(!token.is_cancelled());
+        other.cancel();
+        assert!(token.is_cancelled());
+    }
+}
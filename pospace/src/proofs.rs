@@ -1,58 +1,204 @@
-use crate::bits::from_bits;
-use crate::core::{PlotSeed, PoSpace};
+use crate::constants::PARAM_PLOT_FILTER;
+use crate::core::{PoSpace, NUMBER_OF_TABLES};
+use crate::plot_id::PlotId;
+use crate::verifier::canonical_x_values;
 use crate::Bits;
 use anyhow::Result;
+use bitvec::order::Msb0;
 use bitvec::view::BitView;
 use borsh::{BorshDeserialize, BorshSerialize};
+use log::*;
 
-type QualityString = Vec<u8>;
+pub type QualityString = Vec<u8>;
 
+/// Index of the pair of x values used for the quality, given by the last 5 bits of the challenge
+pub fn quality_index(challenge: &[u8]) -> usize {
+    let mask = (1 << (NUMBER_OF_TABLES - 2)) - 1;
+    challenge.last().map_or(0, |byte| *byte as usize & mask)
+}
+
+/// Hash of the challenge and of the two x values chosen by the challenge
+pub fn quality_string(challenge: &[u8], xvalues: &[u64; 2]) -> QualityString {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(challenge);
+    hasher.update(&xvalues[0].to_le_bytes());
+    hasher.update(&xvalues[1].to_le_bytes());
+    hasher.finalize().as_bytes().to_vec()
+}
+
+/// Whether the plot is looked at for the challenge
+///
+/// The hash of the plot id, the challenge and the signage data must start with
+/// `filter_bits` zero bits, which cannot be known before the challenge.
+pub fn passes_plot_filter(
+    plot_id: &PlotId,
+    challenge: &[u8],
+    signage_data: &[u8],
+    filter_bits: usize,
+) -> bool {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&plot_id.try_to_vec().unwrap());
+    hasher.update(challenge);
+    hasher.update(signage_data);
+    let hash = hasher.finalize();
+    let bits = hash.as_bytes().view_bits::<Msb0>();
+    bits[..filter_bits.min(bits.len())].not_any()
+}
+
+/// Proof of space of a plot for a challenge
+///
+/// The x-values are in the canonical order given by `canonical_x_values`, so a
+/// plot entry has a single valid proof.
 #[derive(BorshSerialize, BorshDeserialize, Eq, PartialEq, Clone, Debug)]
 pub struct Proof {
     pub x_values: Vec<u64>,
     pub challenge: Vec<u8>,
+    pub signage_data: Vec<u8>,
     pub k: usize,
-    pub plot_seed: PlotSeed,
+    pub plot_id: PlotId,
 }
 
 pub struct Prover {
     pospace: PoSpace,
+    plot_filter: usize,
 }
 
 impl Prover {
     pub fn new(pospace: PoSpace) -> Self {
-        Prover { pospace }
+        Prover {
+            pospace,
+            plot_filter: PARAM_PLOT_FILTER,
+        }
     }
 
-    /// Require only 6 disk seeks
-    pub fn get_quality_string(&self, challenge: &[u8]) -> QualityString {
-        let chall_id = from_bits(&challenge.view_bits());
-        let _qual_id = chall_id % 32;
-        let _target: Bits = challenge.view_bits()[0..self.pospace.k].to_bitvec();
-        todo!()
+    /// Changes the number of zero bits required by the plot filter, the proofs
+    /// are only accepted by a verifier using the same filter
+    pub fn with_plot_filter(mut self, filter_bits: usize) -> Self {
+        self.plot_filter = filter_bits;
+        self
     }
 
-    pub fn retrieve_all_proofs(&self, challenge: &[u8]) -> Result<Vec<Proof>> {
+    pub fn passes_plot_filter(&self, challenge: &[u8], signage_data: &[u8]) -> bool {
+        passes_plot_filter(
+            &self.pospace.plot_id,
+            challenge,
+            signage_data,
+            self.plot_filter,
+        )
+    }
+
+    /// Quality strings of every proof matching the challenge, in the same order
+    /// as the proofs returned by `retrieve_all_proofs`
+    ///
+    /// Only one branch of each proof is read, which requires about 6 disk seeks
+    /// after the lookup in table 7. The plot is not read at all if it does not
+    /// pass the plot filter.
+    pub fn get_quality_string(
+        &self,
+        challenge: &[u8],
+        signage_data: &[u8],
+    ) -> Result<Vec<QualityString>> {
+        if !self.passes_plot_filter(challenge, signage_data) {
+            debug!("The plot does not pass the plot filter");
+            return Ok(Vec::new());
+        }
         let target: Bits = challenge.view_bits()[0..self.pospace.k].to_bitvec();
-        let proofs = self.pospace.find_xvalues_from_target(&target)?;
-        let proofs = proofs
+        let xvalues = self
+            .pospace
+            .find_quality_xvalues(&target, quality_index(challenge))?;
+        Ok(xvalues
             .iter()
-            .map(|p| {
-                let x_values = p
-                    .iter()
-                    .map(|e| from_bits(&e.metadata.as_ref().unwrap().view_bits()[..self.pospace.k]))
-                    .collect::<Vec<u64>>();
-                return Proof {
-                    x_values,
-                    challenge: challenge.to_owned(),
-                    k: self.pospace.k,
-                    plot_seed: self.pospace.plot_seed,
-                };
-            })
-            .collect::<Vec<Proof>>();
-        return Ok(proofs);
+            .map(|pair| quality_string(challenge, pair))
+            .collect())
+    }
+
+    pub fn retrieve_all_proofs(&self, challenge: &[u8], signage_data: &[u8]) -> Result<Vec<Proof>> {
+        if !self.passes_plot_filter(challenge, signage_data) {
+            debug!("The plot does not pass the plot filter");
+            return Ok(Vec::new());
+        }
+        let target: Bits = challenge.view_bits()[0..self.pospace.k].to_bitvec();
+        self.pospace
+            .find_xvalues_from_target(&target)?
+            .into_iter()
+            .map(|x_values| self.proof(x_values, challenge, signage_data))
+            .collect()
+    }
+
+    /// Proof behind the quality string at `index` among the ones returned by
+    /// `get_quality_string`
+    ///
+    /// Only this proof is read from the plot, `None` is returned if the plot does
+    /// not pass the plot filter or if there is no proof at this index.
+    pub fn get_full_proof(
+        &self,
+        challenge: &[u8],
+        signage_data: &[u8],
+        index: usize,
+    ) -> Result<Option<Proof>> {
+        if !self.passes_plot_filter(challenge, signage_data) {
+            debug!("The plot does not pass the plot filter");
+            return Ok(None);
+        }
+        let target: Bits = challenge.view_bits()[0..self.pospace.k].to_bitvec();
+        self.pospace
+            .find_xvalues_at(&target, index)?
+            .map(|x_values| self.proof(x_values, challenge, signage_data))
+            .transpose()
+    }
+
+    fn proof(&self, x_values: Vec<u64>, challenge: &[u8], signage_data: &[u8]) -> Result<Proof> {
+        Ok(Proof {
+            x_values: canonical_x_values(self.pospace.k, self.pospace.plot_seed, &x_values)?,
+            challenge: challenge.to_owned(),
+            signage_data: signage_data.to_owned(),
+            k: self.pospace.k,
+            plot_id: self.pospace.plot_id,
+        })
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+
+    use super::*;
+    use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+    use spaceframe_crypto::traits::PrivateKey;
+
+    #[test]
+    fn test_quality_index() {
+        assert_eq!(quality_index(&[0xff, 0x00]), 0);
+        assert_eq!(quality_index(&[0x00, 0xff]), 31);
+        assert_eq!(quality_index(&[0x00, 0x45]), 5);
+        assert_eq!(quality_index(&[]), 0);
+    }
+
+    #[test]
+    fn test_plot_filter() {
+        let public_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+            .unwrap()
+            .public_key();
+        let plot_id = PlotId::new(public_key, public_key, [0; 32]);
+        assert!(passes_plot_filter(&plot_id, &[1, 2, 3], &[4, 5], 0));
+
+        // About one signage data out of 2^filter_bits passes the filter
+        let passed = (0..4096u32)
+            .filter(|i| passes_plot_filter(&plot_id, &[1, 2, 3], &i.to_le_bytes(), 4))
+            .count();
+        assert!(passed > 128 && passed < 384, "{} passed", passed);
+        assert!((0..4096u32).all(|i| !passes_plot_filter(
+            &plot_id,
+            &[1, 2, 3],
+            &i.to_le_bytes(),
+            256
+        )));
+    }
+
+    #[test]
+    fn test_quality_string() {
+        let quality = quality_string(&[1, 2, 3], &[4, 5]);
+        assert_eq!(quality.len(), 32);
+        assert_ne!(quality, quality_string(&[1, 2, 3], &[5, 4]));
+        assert_ne!(quality, quality_string(&[1, 2, 4], &[4, 5]));
+    }
+}
@@ -1,310 +1,485 @@
-use crate::storage::{deserialize, plotentry_size, serialize, store_table_part, PlotEntry};
+use crate::config::PlottingConfig;
+use crate::constants::PARAM_EXT;
+use crate::storage::{deserialize, plotentry_size, serialize, ChunkReader, PlotEntry};
 use anyhow::{Context, Result};
 use log::*;
-use std::fmt::Debug;
-use std::fs::{read_dir, remove_file, rename, File};
-use std::io::{Read, Write};
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 
-use crate::error::{MergeChunkError, SortError};
-use crate::{table_final_filename_format, table_sorted_filename_format};
-use std::collections::VecDeque;
+use crate::error::SortError;
+use crate::{table_bucket_filename_format, table_final_filename_format};
 
-pub fn sort_table_part(
-    path: &Path,
+/// Number of bits of fx sorted by each pass of the radix sort
+const RADIX_BITS: usize = 8;
+
+/// Below this number of entries a range is sorted by insertion instead
+const INSERTION_SORT_THRESHOLD: usize = 32;
+
+/// Scatters the entries of a table into bucket files according to the high bits of fx
+///
+/// The fx values are uniformly distributed, so every bucket holds about the same
+/// number of entries and can be sorted in memory on its own.
+pub struct BucketWriter {
+    path: PathBuf,
     table_index: usize,
-    part_index: usize,
     k: usize,
-) -> Result<PathBuf> {
-    info!("[Table {}] Sorting part {} ...", table_index, part_index);
-    let mut buffer = Vec::new();
-    let mut file = File::open(&path).context(format!(
-        "Could not open plot file part {} of table {}",
-        part_index, table_index
-    ))?;
-    file.read_to_end(&mut buffer).context(format!(
-        "Could not read plot file part {} of table {}",
-        part_index, table_index
-    ))?;
-    let mut entries = deserialize(&buffer, plotentry_size(table_index, k)).context(format!(
-        "Could not deserialize part {} of table {}",
-        part_index, table_index
-    ))?;
+    bucket_shift: usize,
+    entries_per_bucket: usize,
+    buffers: Vec<Vec<PlotEntry>>,
+}
 
-    entries.sort_unstable();
+/// Number of buckets a table is scattered into
+///
+/// A table has at most `2 << k` entries, `1 << k` for the first one. There are
+/// enough buckets for twice that many entries to be sorted within the memory
+/// budget, which leaves room for the buckets that get more than their share.
+pub fn bucket_count(table_index: usize, k: usize, config: &PlottingConfig) -> usize {
+    let max_entries = if table_index == 1 { 1 << k } else { 2 << k };
+    let entries_per_chunk = config.entries_per_chunk(plotentry_size(table_index, k));
+    (2 * max_entries / entries_per_chunk + 1)
+        .next_power_of_two()
+        .max(config.buckets())
+        .min(1 << (k + PARAM_EXT))
+}
 
-    let out_path = path.parent().unwrap().join(format!(
-        table_sorted_filename_format!(),
-        table_index, part_index
-    ));
+impl BucketWriter {
+    /// The memory budget is shared between the buffers of every bucket, `buckets`
+    /// must be the `bucket_count` of the config the table is then sorted with
+    pub fn new(
+        path: &Path,
+        table_index: usize,
+        k: usize,
+        buckets: usize,
+        config: &PlottingConfig,
+    ) -> Self {
+        let fx_bits = k + PARAM_EXT;
+        let entries_per_chunk = config.entries_per_chunk(plotentry_size(table_index, k));
+        BucketWriter {
+            path: path.to_owned(),
+            table_index,
+            k,
+            bucket_shift: fx_bits - buckets.trailing_zeros() as usize,
+            entries_per_bucket: (entries_per_chunk / buckets).max(1),
+            buffers: (0..buckets).map(|_| Vec::new()).collect(),
+        }
+    }
 
-    store_table_part(&entries, &out_path).context(format!(
-        "Could not store table {} part {} to disk",
-        table_index, part_index
-    ))?;
-    info!("[Table {}] Part {} sorted", table_index, part_index);
-    Ok(out_path)
+    pub fn push(&mut self, entry: PlotEntry) -> Result<()> {
+        let bucket = (entry.fx >> self.bucket_shift) as usize;
+        self.buffers[bucket].push(entry);
+        if self.buffers[bucket].len() >= self.entries_per_bucket {
+            self.flush_bucket(bucket)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining entries to disk
+    pub fn finish(mut self) -> Result<()> {
+        for bucket in 0..self.buffers.len() {
+            self.flush_bucket(bucket)?;
+        }
+        Ok(())
+    }
+
+    fn flush_bucket(&mut self, bucket: usize) -> Result<()> {
+        if self.buffers[bucket].is_empty() {
+            return Ok(());
+        }
+        let bucket_path = self.path.join(format!(
+            table_bucket_filename_format!(),
+            self.table_index, bucket
+        ));
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&bucket_path)
+            .context(format!("Could not open bucket file {:?}", bucket_path))?
+            .write_all(&serialize(&self.buffers[bucket], self.table_index, self.k)?)
+            .context(format!(
+                "Could not write bucket {} of table {} to disk",
+                bucket, self.table_index
+            ))?;
+        self.buffers[bucket].clear();
+        Ok(())
+    }
 }
 
+/// Sorts the bucket files of a table one after the other and concatenates
+/// them in the final file of the table
 pub fn sort_table_on_disk(
     table_index: usize,
     path: &Path,
-    entries_per_chunk: usize,
+    config: &PlottingConfig,
     k: usize,
 ) -> Result<()> {
-    // Sort each bucket
-    let mut chunks_count = 0;
-    let mut parts = Vec::new();
-
-    // Sort individual table parts
-    for (index, entry) in read_dir(path)
-        .context(format!("Could not read directory: {:?}", path))?
-        .filter_map(Result::ok)
-        .map(|x| x.path())
-        .filter(|e| {
-            e.is_file()
-                && e.file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .starts_with(format!("table{}_raw_", table_index).as_str())
-        })
-        .enumerate()
-    {
-        let part_path = sort_table_part(&entry, table_index, index + 1, k)?;
-        parts.push(part_path);
-        chunks_count += 1;
-    }
-
-    // K-Way Merge sort
+    sort_table_streaming(table_index, path, config, k, |_| Ok(()))
+}
 
-    if chunks_count > 1 {
-        info!("[Table {}] K-Way merging ...", table_index);
+/// Same as `sort_table_on_disk`, each sorted bucket is also given to `on_bucket`
+/// once written so that the next table can be computed from it without reading
+/// the final file again
+pub fn sort_table_streaming(
+    table_index: usize,
+    path: &Path,
+    config: &PlottingConfig,
+    k: usize,
+    mut on_bucket: impl FnMut(Vec<PlotEntry>) -> Result<()>,
+) -> Result<()> {
+    let final_path = path.join(format!(table_final_filename_format!(), table_index));
+    let mut output = BufWriter::new(
+        File::create(&final_path).context(format!("Failed to create file: {:?}", final_path))?,
+    );
+
+    let buckets = bucket_count(table_index, k, config);
+    let bucket_shift = k + PARAM_EXT - buckets.trailing_zeros() as usize;
+    for bucket in 0..buckets {
+        let bucket_path = path.join(format!(
+            table_bucket_filename_format!(),
+            table_index, bucket
+        ));
+        if !bucket_path.exists() {
+            continue;
+        }
 
-        let mut state = KWayMerge::new(
-            &parts,
-            plotentry_size(table_index, k),
-            entries_per_chunk,
-            &path.join(format!(table_final_filename_format!(), table_index)),
+        sort_bucket(
+            &bucket_path,
+            bucket_shift,
             table_index,
+            k,
+            config,
+            &mut output,
+            &mut on_bucket,
         )
         .context(format!(
-            "Could not start k-way merge for table {}",
-            table_index
+            "Could not sort bucket {} of table {}",
+            bucket, table_index
         ))?;
-
-        while state
-            .run_iteration()
-            .context("An error occurred during a k-way merge iteration")?
-            != KWayMergeState::Done
-        {}
-
-        info!("[Table {}] K-Way merge done", table_index);
-    } else {
-        rename(
-            path.join(format!(table_sorted_filename_format!(), table_index, 1)),
-            path.join(format!(table_final_filename_format!(), table_index)),
-        )
-        .or_else(|e| Err(SortError::RenameError(e.kind())))
-        .context(format!(
-            "Could not rename plot file for table {}",
-            table_index
-        ))?
     }
 
-    info!("[Table {}] Cleaning intermediate files ...", table_index);
-    read_dir(path)
-        .context(format!(
-            "Could not read directory ({:?}) to clean intermediate files",
-            path
-        ))?
-        .filter_map(Result::ok)
-        .map(|x| x.path())
-        .filter(|e| {
-            let filename = e.file_name().unwrap().to_str().unwrap();
-            return e.is_file()
-                && (filename.starts_with(format!("table{}_raw_", table_index).as_str())
-                    || filename.starts_with(format!("table{}_sorted_", table_index).as_str()));
-        })
-        .map(|f| {
-            remove_file(&f).or_else(|e| Err(SortError::DeleteError(f.to_owned(), e.kind()).into()))
-        })
-        .collect::<Result<()>>()
-        .context(format!(
-            "Could not clean intermediate files for table {}",
-            table_index
-        ))?;
+    output.flush().context(format!(
+        "Could not write final file of table {}",
+        table_index
+    ))?;
+    info!("[Table {}] {} buckets sorted", table_index, buckets);
 
     Ok(())
 }
 
-#[derive(Debug, PartialEq)]
-enum KWayMergeState {
-    Success,
-    Done,
-}
-
-#[derive(Debug)]
-struct KWayMerge {
-    entries_per_chunk: usize,
-    output_file: File,
-    chunks: Vec<MergeChunk>,
-    output: Vec<PlotEntry>,
-    iter_count: usize,
-    item_count: usize,
+/// Sorts a bucket file whose entries only differ on the `low_bits` lowest bits of
+/// fx, then appends it to `output` and gives it to `on_bucket`
+///
+/// The fx values are not spread evenly enough for every bucket to fit in the
+/// memory budget, a larger bucket is split again on the next bits of fx.
+fn sort_bucket(
+    bucket_path: &Path,
+    low_bits: usize,
     table_index: usize,
-}
-
-impl KWayMerge {
-    pub fn new(
-        paths: &[PathBuf],
-        entry_size: usize,
-        entries_per_chunk: usize,
-        output_file_path: &Path,
-        table_index: usize,
-    ) -> Result<Self> {
-        let chunk_size = entries_per_chunk / (paths.len() - 1) * entry_size;
-        let mut state = Self {
-            entries_per_chunk,
-            chunks: Vec::new(),
-            output: Vec::new(),
-            iter_count: 0,
-            item_count: 0,
-            output_file: File::create(output_file_path)
-                .context(format!("Failed to create file: {:?}", output_file_path))?,
-            table_index,
-        };
-
-        let mut id_counter = 1;
-
-        for path in paths {
-            let file = File::open(path)?;
-            let file_size = file.metadata()?.len() as usize;
-            let merge_chunk = MergeChunk {
-                id: id_counter,
-                file,
-                entry_size,
-                total_size: file_size,
-                remaining_size: file_size,
-                content: VecDeque::new(),
-                chunk_size,
-            };
-            state.chunks.push(merge_chunk);
-            id_counter += 1;
+    k: usize,
+    config: &PlottingConfig,
+    output: &mut impl Write,
+    on_bucket: &mut impl FnMut(Vec<PlotEntry>) -> Result<()>,
+) -> Result<()> {
+    let entry_size = plotentry_size(table_index, k);
+    let max_entries = config.entries_per_chunk(entry_size);
+    let entries_count = bucket_path
+        .metadata()
+        .context(format!("Could not read the size of {:?}", bucket_path))?
+        .len() as usize
+        / entry_size;
+
+    if entries_count > max_entries && low_bits > 0 {
+        let split_bits = ((2 * entries_count / max_entries + 1)
+            .next_power_of_two()
+            .trailing_zeros() as usize)
+            .min(low_bits);
+        let shift = low_bits - split_bits;
+        for part in split_bucket(bucket_path, shift, split_bits, table_index, k, config)? {
+            sort_bucket(&part, shift, table_index, k, config, output, on_bucket)?;
         }
+        return Ok(());
+    }
 
-        Ok(state)
+    let mut buffer = Vec::new();
+    File::open(bucket_path)
+        .context(format!("Could not open {:?}", bucket_path))?
+        .read_to_end(&mut buffer)
+        .context(format!("Could not read {:?}", bucket_path))?;
+    let mut entries = deserialize(&buffer, table_index, k)
+        .context(format!("Could not deserialize {:?}", bucket_path))?;
+    drop(buffer);
+
+    if entries.len() > max_entries {
+        warn!(
+            "[Table {}] {} entries share the same fx, more than the memory budget allows ({})",
+            table_index,
+            entries.len(),
+            max_entries
+        );
     }
 
-    pub fn run_iteration(&mut self) -> Result<KWayMergeState> {
-        // Load new data into chunks if they are empty
-        for chunk in self.chunks.iter_mut() {
-            // Refill chunk
-            chunk.refill().context("Failed to refill chunk")?;
-        }
+    radix_sort(&mut entries, low_bits);
+    output
+        .write_all(&serialize(&entries, table_index, k)?)
+        .context(format!("Could not write table {} to disk", table_index))?;
 
-        // Find the min
-        let min = self
-            .find_min_chunk()
-            .context("Failed to find minimum among chunks")?;
-        let min_chunk = &mut self.chunks[min];
-
-        // Move the minimum value to the output vec
-        // Delete the minimum from the chunk (increase the index)
-        self.output.push(
-            min_chunk
-                .content
-                .pop_front()
-                .ok_or(MergeChunkError::MinChunkIsEmpty)?,
-        );
+    remove_file(bucket_path)
+        .map_err(|e| SortError::DeleteError(bucket_path.to_owned(), e.kind()))?;
+    on_bucket(entries)
+}
 
-        // Write output if it is full
-        if self.output.len() >= self.entries_per_chunk {
-            self.write_output().context("Failed to write output data")?;
-            self.output.clear();
+/// Scatters a bucket file into `1 << split_bits` files on the bits of fx above
+/// `shift`, chunk by chunk, and returns the files that are not empty in the
+/// order of their fx
+///
+/// Every chunk is sorted before it is scattered, so that the parts are written
+/// in the order of their fx.
+fn split_bucket(
+    bucket_path: &Path,
+    shift: usize,
+    split_bits: usize,
+    table_index: usize,
+    k: usize,
+    config: &PlottingConfig,
+) -> Result<Vec<PathBuf>> {
+    let parts = (0..1 << split_bits)
+        .map(|part| {
+            let mut part_path = bucket_path.as_os_str().to_owned();
+            part_path.push(format!("_{}", part));
+            PathBuf::from(part_path)
+        })
+        .collect::<Vec<PathBuf>>();
+    let mut writers = parts
+        .iter()
+        .map(|part_path| {
+            File::create(part_path)
+                .map(BufWriter::new)
+                .context(format!("Could not create {:?}", part_path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut counts = vec![0; parts.len()];
+    let mask = (1 << split_bits) - 1;
+    let part_of = |entry: &PlotEntry| (entry.fx >> shift) as usize & mask;
+
+    let file = File::open(bucket_path).context(format!("Could not open {:?}", bucket_path))?;
+    for chunk in ChunkReader::from_file(file, table_index, k, config)? {
+        let mut chunk = chunk?;
+        radix_sort(&mut chunk, shift + split_bits);
+        for entries in chunk.chunk_by(|a, b| part_of(a) == part_of(b)) {
+            let part = part_of(&entries[0]);
+            counts[part] += entries.len();
+            writers[part]
+                .write_all(&serialize(entries, table_index, k)?)
+                .context(format!("Could not write {:?}", parts[part]))?;
         }
+    }
+    for (writer, part_path) in writers.iter_mut().zip(&parts) {
+        writer
+            .flush()
+            .context(format!("Could not write {:?}", part_path))?;
+    }
+    drop(writers);
+    remove_file(bucket_path)
+        .map_err(|e| SortError::DeleteError(bucket_path.to_owned(), e.kind()))?;
+
+    let mut kept = Vec::new();
+    for (part_path, count) in parts.into_iter().zip(counts) {
+        if count == 0 {
+            remove_file(&part_path)
+                .map_err(|e| SortError::DeleteError(part_path.to_owned(), e.kind()))?;
+        } else {
+            kept.push(part_path);
+        }
+    }
+    Ok(kept)
+}
 
-        // Keeping only unfinished chunks
-        self.chunks.retain(|x| !x.is_done());
-
-        if self.chunks.len() == 0 {
-            self.write_output().context("Failed to write output data")?;
-            info!(
-                "[Table {}] Final part {} written",
-                self.table_index, self.iter_count
-            );
-            return Ok(KWayMergeState::Done);
+/// In-place MSD radix sort (American flag sort) of the entries on the
+/// `key_bits` lowest bits of fx, the higher bits must be the same for all
+/// the entries
+///
+/// No memory is needed besides the entries, the entries with the same fx do
+/// not keep their order.
+pub fn radix_sort(entries: &mut [PlotEntry], key_bits: usize) {
+    if entries.len() <= INSERTION_SORT_THRESHOLD {
+        for i in 1..entries.len() {
+            let mut j = i;
+            while j > 0 && entries[j - 1].fx > entries[j].fx {
+                entries.swap(j - 1, j);
+                j -= 1;
+            }
         }
+        return;
+    }
+    if key_bits == 0 {
+        return;
+    }
 
-        Ok(KWayMergeState::Success)
+    let shift = key_bits.saturating_sub(RADIX_BITS);
+    let mask = (1 << (key_bits - shift)) - 1;
+    let digit = |entry: &PlotEntry| ((entry.fx >> shift) & mask) as usize;
+
+    let mut ends = [0usize; 1 << RADIX_BITS];
+    for entry in entries.iter() {
+        ends[digit(entry)] += 1;
+    }
+    let mut position = 0;
+    for end in ends.iter_mut() {
+        position += *end;
+        *end = position;
     }
 
-    pub fn find_min_chunk(&self) -> Result<usize> {
-        if self.chunks.iter().all(|c| c.content.get(0).is_some()) {
-            Ok(self
-                .chunks
-                .iter()
-                .map(|c| &c.content[0])
-                .collect::<Vec<&PlotEntry>>()
-                .iter()
-                .enumerate()
-                .min_by_key(|&(_, x)| x)
-                .ok_or(MergeChunkError::EmptyChunksWhileFetchingMininum)?
-                .0)
-        } else {
-            return Err(MergeChunkError::EmptyChunksWhileFetchingMininum.into());
+    // Every entry is swapped into the next free slot of its digit
+    let mut next = [0usize; 1 << RADIX_BITS];
+    next[1..].copy_from_slice(&ends[..ends.len() - 1]);
+    let starts = next;
+    for current in 0..next.len() {
+        while next[current] < ends[current] {
+            let target = digit(&entries[next[current]]);
+            if target != current {
+                entries.swap(next[current], next[target]);
+            }
+            next[target] += 1;
         }
     }
 
-    fn write_output(&mut self) -> Result<()> {
-        self.iter_count += 1;
-        self.item_count += self.output.len();
-        if !self.output.is_empty() {
-            let bin_data = serialize(&self.output)?;
-            self.output_file.write_all(&bin_data)?;
-        }
-        Ok(())
+    for (start, end) in starts.iter().zip(&ends) {
+        radix_sort(&mut entries[*start..*end], shift);
     }
 }
 
-#[derive(Debug)]
-struct MergeChunk {
-    id: u32,
-    file: File, // TODO don't keep the file open to prevent "Too many open files" error
-    content: VecDeque<PlotEntry>,
-    entry_size: usize,
-    total_size: usize,
-    chunk_size: usize,
-    remaining_size: usize,
+/// Total size of the bucket files of a table
+pub fn buckets_size(path: &Path, table_index: usize, buckets: usize) -> u64 {
+    (0..buckets)
+        .filter_map(|bucket| {
+            path.join(format!(
+                table_bucket_filename_format!(),
+                table_index, bucket
+            ))
+            .metadata()
+            .ok()
+        })
+        .map(|m| m.len())
+        .sum()
 }
 
-impl MergeChunk {
-    pub fn refill(&mut self) -> Result<()> {
-        if self.content.len() == 0 && self.remaining_size > 0 {
-            let amount;
-            let mut buffer;
-            if self.remaining_size > self.chunk_size {
-                // Read only 1 chunk
-                buffer = vec![0u8; self.chunk_size];
-                self.file.read_exact(&mut buffer)?;
-                amount = self.chunk_size;
-            } else {
-                // Read to the end
-                buffer = Vec::new();
-                amount = self.file.read_to_end(&mut buffer)?;
-            }
-
-            self.remaining_size -= amount;
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::f1_calculator::F1Calculator;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_radix_sort() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for (len, key_bits) in [
+            (0, 18),
+            (1, 18),
+            (20, 18),
+            (5000, 18),
+            (5000, 11),
+            (3000, 0),
+        ] {
+            // Few distinct fx so that some entries share the same fx, the bits
+            // above `key_bits` are the same for every entry
+            let high_bits = 0b101 << key_bits;
+            let entries = (0..len)
+                .map(|id| PlotEntry {
+                    fx: high_bits | rng.gen_range(0..1u64 << key_bits) & !0xf,
+                    metadata: Some(id),
+                    position: None,
+                    offset: None,
+                })
+                .collect::<Vec<PlotEntry>>();
+
+            let mut sorted = entries.clone();
+            radix_sort(&mut sorted, key_bits);
+            // Stable sort on fx, which the buckets used to be sorted with
+            let mut expected = entries;
+            expected.sort_by_key(|entry| entry.fx);
+            assert_eq!(
+                sorted.iter().map(|e| e.fx).collect::<Vec<u64>>(),
+                expected.iter().map(|e| e.fx).collect::<Vec<u64>>()
+            );
 
-            // Deserilalize entries
-            let entries = deserialize(&buffer, self.entry_size)?;
-            self.content = VecDeque::from(entries);
+            // Only the order of the entries with the same fx may differ
+            sorted.sort_by_key(|entry| (entry.fx, entry.metadata));
+            assert_eq!(sorted, expected);
         }
+    }
 
+    #[test]
+    fn test_buckets_fit_in_budget() -> Result<()> {
+        let k = 14;
+        let dir = TempDir::new("spaceframe_sort_budget").unwrap();
+        let config = PlottingConfig::new(32 * 1024, 1, 2)?;
+        let buckets = bucket_count(1, k, &config);
+        assert!(buckets > config.buckets());
+
+        let f1_calculator = F1Calculator::new(k, [7; 32]);
+        let mut writer = BucketWriter::new(dir.path(), 1, k, buckets, &config);
+        for (x, fx) in f1_calculator
+            .calculate_f1_range(0, 1 << k)
+            .into_iter()
+            .enumerate()
+        {
+            writer.push(PlotEntry {
+                fx,
+                metadata: Some(x as u128),
+                position: None,
+                offset: None,
+            })?;
+        }
+        writer.finish()?;
+
+        let max_entries = config.entries_per_chunk(plotentry_size(1, k));
+        let mut entries = 0;
+        sort_table_streaming(1, dir.path(), &config, k, |bucket| {
+            assert!(bucket.len() <= max_entries);
+            entries += bucket.len();
+            Ok(())
+        })?;
+        assert_eq!(entries, 1 << k);
         Ok(())
     }
 
-    pub fn is_done(&self) -> bool {
-        return self.content.len() == 0 && self.remaining_size == 0;
+    #[test]
+    fn test_sort_table_streaming() -> Result<()> {
+        let k = 12;
+        let dir = TempDir::new("spaceframe_sort").unwrap();
+        let config = PlottingConfig::new(1 << 12, 1, 4)?;
+        let buckets = bucket_count(1, k, &config);
+        let mut writer = BucketWriter::new(dir.path(), 1, k, buckets, &config);
+        for x in 0..1000u64 {
+            writer.push(PlotEntry {
+                fx: (x * 7919) % (1 << (k + PARAM_EXT)),
+                metadata: Some(x as u128),
+                position: None,
+                offset: None,
+            })?;
+        }
+        writer.finish()?;
+        assert_eq!(
+            buckets_size(dir.path(), 1, buckets),
+            1000 * plotentry_size(1, k) as u64
+        );
+
+        let mut streamed = Vec::new();
+        sort_table_streaming(1, dir.path(), &config, k, |bucket| {
+            streamed.extend(bucket);
+            Ok(())
+        })?;
+        assert_eq!(buckets_size(dir.path(), 1, buckets), 0);
+        assert!(streamed.windows(2).all(|w| w[0].fx <= w[1].fx));
+
+        // The streamed entries are the content of the final file
+        let on_disk = ChunkReader::new(dir.path(), 1, k, &config)?
+            .collect::<Result<Vec<Vec<PlotEntry>>>>()?
+            .concat();
+        assert_eq!(on_disk, streamed);
+        Ok(())
     }
 }
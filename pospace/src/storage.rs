@@ -1,46 +1,40 @@
+use crate::config::PlottingConfig;
+use crate::constants::PARAM_EXT;
 use crate::core::collation_size_bits;
 use crate::error::StorageError;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use std::fs::{copy, remove_file, rename};
 use std::io::Read;
 use std::{fs::File, io::Write, path::Path};
-use sysinfo::SystemExt;
-
-lazy_static! {
-    pub static ref ENTRIES_PER_CHUNK: usize = {
-        let mut system = sysinfo::System::new_all();
-        system.refresh_all();
-        let power: u64 = (system.total_memory() as f64).log(2f64) as u64;
-        1 << power
-    };
-}
 
 #[macro_export]
-macro_rules! table_raw_filename_format {
+macro_rules! table_bucket_filename_format {
     () => {
-        "table{}_raw_{}"
+        "table{}_bucket_{}"
     };
 }
 
 #[macro_export]
-macro_rules! table_sorted_filename_format {
+macro_rules! table_final_filename_format {
     () => {
-        "table{}_sorted_{}"
+        "table{}_final"
     };
 }
 
 #[macro_export]
-macro_rules! table_final_filename_format {
+macro_rules! table_tmp_filename_format {
     () => {
-        "table{}_final"
+        "table{}_tmp"
     };
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct PlotEntry {
     pub fx: u64,
-    pub metadata: Option<Vec<u8>>,
+    /// Value of the `collation_size_bits(table_index + 1, k)` bits of metadata
+    pub metadata: Option<u128>,
     pub position: Option<u64>,
     pub offset: Option<u64>,
 }
@@ -57,89 +51,336 @@ impl PartialOrd for PlotEntry {
     }
 }
 
-pub fn store_table_part(buffer: &[PlotEntry], path: &Path) -> Result<()> {
+pub fn store_table_part(
+    buffer: &[PlotEntry],
+    path: &Path,
+    table_index: usize,
+    k: usize,
+) -> Result<()> {
     let mut new_file = File::create(path).context(format!("Could not create file {:?}", path))?;
-    let bin_data = serialize(buffer).context("Could not serialize table part")?;
+    let bin_data = serialize(buffer, table_index, k).context("Could not serialize table part")?;
     new_file
         .write_all(&bin_data)
         .context("Could not write table part to disk")?;
     Ok(())
 }
 
-pub fn store_raw_table_part(
-    table_index: usize,
-    part_index: usize,
-    buffer: &[PlotEntry],
-    path: &Path,
-) -> Result<()> {
-    store_table_part(
-        buffer,
-        &path.join(format!(
-            table_raw_filename_format!(),
-            table_index, part_index
-        )),
-    )
+/// Writes the `size` lowest bits of `value` at bit `offset`, the buffer must be zeroed
+fn write_bits(buffer: &mut [u8], offset: usize, value: u64, size: usize) {
+    let mut written = 0;
+    while written < size {
+        let position = offset + written;
+        let shift = position % 8;
+        let amount = (8 - shift).min(size - written);
+        let bits = (value >> written) & ((1 << amount) - 1);
+        buffer[position / 8] |= (bits << shift) as u8;
+        written += amount;
+    }
 }
 
-fn ser(entry: &PlotEntry) -> Result<Vec<u8>> {
-    Ok(bincode::serialize(entry).or(Err(StorageError::SerializationError))?)
+/// Reads `size` bits starting at bit `offset`
+fn read_bits(buffer: &[u8], offset: usize, size: usize) -> u64 {
+    let mut value = 0;
+    let mut read = 0;
+    while read < size {
+        let position = offset + read;
+        let shift = position % 8;
+        let amount = (8 - shift).min(size - read);
+        let bits = (buffer[position / 8] as u64 >> shift) & ((1 << amount) - 1);
+        value |= bits << read;
+        read += amount;
+    }
+    value
 }
 
-pub fn serialize(buffer: &[PlotEntry]) -> Result<Vec<u8>> {
-    let res = buffer.iter().map(ser).collect::<Result<Vec<Vec<u8>>>>()?;
-    Ok(res.iter().flatten().cloned().collect::<Vec<u8>>())
+/// Writes the metadata with its most significant bit first, like the bit vector
+/// the metadata used to be stored as
+fn write_metadata(buffer: &mut [u8], offset: usize, value: u128, size: usize) {
+    let reversed = (value << (128 - size)).reverse_bits();
+    write_bits(buffer, offset, reversed as u64, size.min(64));
+    if size > 64 {
+        write_bits(buffer, offset + 64, (reversed >> 64) as u64, size - 64);
+    }
 }
 
-fn deser(chunk: &[u8]) -> Result<PlotEntry> {
-    Ok(bincode::deserialize(&chunk).or(Err(StorageError::DeserializationError))?)
+/// Inverse of `write_metadata`
+fn read_metadata(buffer: &[u8], offset: usize, size: usize) -> u128 {
+    let mut reversed = read_bits(buffer, offset, size.min(64)) as u128;
+    if size > 64 {
+        reversed |= (read_bits(buffer, offset + 64, size - 64) as u128) << 64;
+    }
+    reversed.reverse_bits() >> (128 - size)
 }
 
-pub fn deserialize(buffer: &[u8], entry_size: usize) -> Result<Vec<PlotEntry>> {
-    buffer
-        .chunks(entry_size)
-        .map(deser)
-        .collect::<Result<Vec<PlotEntry>>>()
+fn checked_value(value: Option<u64>, size: usize) -> Result<u64> {
+    let value = value.ok_or(StorageError::SerializationError)?;
+    if size < 64 && value >> size != 0 {
+        return Err(StorageError::SerializationError.into());
+    }
+    Ok(value)
+}
+
+/// Packs an entry in `buffer`, which must be zeroed and `plotentry_size` bytes long
+fn encode_entry(entry: &PlotEntry, buffer: &mut [u8], table_index: usize, k: usize) -> Result<()> {
+    let fx_size = k + PARAM_EXT;
+    let metadata_size = metadata_size_bits(table_index, k);
+    let position_size = position_size_bits(k);
+
+    write_bits(buffer, 0, checked_value(Some(entry.fx), fx_size)?, fx_size);
+
+    let mut offset = fx_size;
+    if metadata_size > 0 {
+        let metadata = entry.metadata.ok_or(StorageError::SerializationError)?;
+        if metadata_size < 128 && metadata >> metadata_size != 0 {
+            return Err(StorageError::SerializationError.into());
+        }
+        write_metadata(buffer, offset, metadata, metadata_size);
+        offset += metadata_size;
+    }
+
+    if table_index > 1 {
+        let position = checked_value(entry.position, position_size)?;
+        let entry_offset = checked_value(entry.offset, position_size)?;
+        write_bits(buffer, offset, position, position_size);
+        write_bits(buffer, offset + position_size, entry_offset, position_size);
+    }
+
+    Ok(())
+}
+
+fn decode_entry(buffer: &[u8], table_index: usize, k: usize) -> PlotEntry {
+    let fx_size = k + PARAM_EXT;
+    let metadata_size = metadata_size_bits(table_index, k);
+    let position_size = position_size_bits(k);
+
+    let metadata = if metadata_size > 0 {
+        read_metadata(buffer, fx_size, metadata_size)
+    } else {
+        0
+    };
+
+    let (position, offset) = if table_index > 1 {
+        let offset = fx_size + metadata_size;
+        (
+            Some(read_bits(buffer, offset, position_size)),
+            Some(read_bits(buffer, offset + position_size, position_size)),
+        )
+    } else {
+        (None, None)
+    };
+
+    PlotEntry {
+        fx: read_bits(buffer, 0, fx_size),
+        metadata: Some(metadata),
+        position,
+        offset,
+    }
+}
+
+/// Packs the entries of a table with a fixed width of `plotentry_size` bytes
+pub fn serialize(buffer: &[PlotEntry], table_index: usize, k: usize) -> Result<Vec<u8>> {
+    let entry_size = plotentry_size(table_index, k);
+    let mut bin_data = vec![0u8; buffer.len() * entry_size];
+    for (entry, chunk) in buffer.iter().zip(bin_data.chunks_exact_mut(entry_size)) {
+        encode_entry(entry, chunk, table_index, k)?;
+    }
+    Ok(bin_data)
+}
+
+pub fn deserialize(buffer: &[u8], table_index: usize, k: usize) -> Result<Vec<PlotEntry>> {
+    let entry_size = plotentry_size(table_index, k);
+    if !buffer.len().is_multiple_of(entry_size) {
+        return Err(StorageError::DeserializationError.into());
+    }
+    Ok(buffer
+        .chunks_exact(entry_size)
+        .map(|chunk| decode_entry(chunk, table_index, k))
+        .collect())
+}
+
+/// Size in bits of the metadata kept in the entries of a table
+fn metadata_size_bits(table_index: usize, k: usize) -> usize {
+    collation_size_bits(table_index + 1, k)
+}
+
+/// Size in bits of a position or an offset, a table holds at most 2^(k+1) entries
+fn position_size_bits(k: usize) -> usize {
+    k + 1
+}
+
+/// Size in bits of an entry: fx, metadata, then position and offset except in table 1
+pub fn plotentry_size_bits(table_index: usize, k: usize) -> usize {
+    let fx_size = k + PARAM_EXT;
+    let metadata_size = metadata_size_bits(table_index, k);
+    match table_index {
+        1 => fx_size + metadata_size,
+        _ => fx_size + metadata_size + 2 * position_size_bits(k),
+    }
 }
 
-/// Size in bytes
+/// Size in bytes, every entry starts on a byte boundary
 pub fn plotentry_size(table_index: usize, k: usize) -> usize {
-    let metadata_size = (collation_size_bits(table_index + 1, k) as f64 / 8 as f64).ceil() as usize;
-    let position_size = 8;
-    let offset_size = 8;
-    let fx_size = 8;
-
-    return 11
-        + match table_index {
-            1 => fx_size + metadata_size,
-            7 => fx_size + offset_size + position_size,
-            _ => fx_size + metadata_size + offset_size + position_size,
-        };
+    plotentry_size_bits(table_index, k).div_ceil(8)
+}
+
+/// Entry of a table once the plot has been compressed
+///
+/// Table 1 only keeps the x values, tables 2 to 6 only keep the line point
+/// pointing to the previous table and table 7 keeps the first k bits of fx
+/// and the line point.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CompressedEntry {
+    pub x: Option<u64>,
+    pub fx: Option<u64>,
+    pub line_point: Option<u128>,
+}
+
+/// Size in bytes of an entry of a compressed table
+pub fn compressed_entry_size(table_index: usize, k: usize) -> usize {
+    let x_size = x_size(k);
+    let line_point_size = line_point_size(k);
+    match table_index {
+        1 => x_size,
+        7 => x_size + line_point_size,
+        _ => line_point_size,
+    }
+}
+
+/// Size in bytes of a x value or of the first k bits of fx
+fn x_size(k: usize) -> usize {
+    k.div_ceil(8)
+}
+
+/// Size in bytes of a line point, positions are less than 2^(k+1)
+fn line_point_size(k: usize) -> usize {
+    (2 * k + 1).div_ceil(8)
+}
+
+fn write_uint(buffer: &mut Vec<u8>, value: u128, size: usize) {
+    buffer.extend_from_slice(&value.to_le_bytes()[..size]);
+}
+
+fn read_uint(bytes: &[u8]) -> u128 {
+    let mut value = [0u8; 16];
+    value[..bytes.len()].copy_from_slice(bytes);
+    u128::from_le_bytes(value)
+}
+
+pub fn serialize_compressed(
+    buffer: &[CompressedEntry],
+    table_index: usize,
+    k: usize,
+) -> Result<Vec<u8>> {
+    let mut bin_data = Vec::with_capacity(buffer.len() * compressed_entry_size(table_index, k));
+    for entry in buffer {
+        match table_index {
+            1 => {
+                let x = entry.x.ok_or(StorageError::SerializationError)?;
+                write_uint(&mut bin_data, x as u128, x_size(k));
+            }
+            7 => {
+                let fx = entry.fx.ok_or(StorageError::SerializationError)?;
+                let line_point = entry.line_point.ok_or(StorageError::SerializationError)?;
+                write_uint(&mut bin_data, fx as u128, x_size(k));
+                write_uint(&mut bin_data, line_point, line_point_size(k));
+            }
+            _ => {
+                let line_point = entry.line_point.ok_or(StorageError::SerializationError)?;
+                write_uint(&mut bin_data, line_point, line_point_size(k));
+            }
+        }
+    }
+    Ok(bin_data)
+}
+
+pub fn deserialize_compressed(
+    buffer: &[u8],
+    table_index: usize,
+    k: usize,
+) -> Result<Vec<CompressedEntry>> {
+    let entry_size = compressed_entry_size(table_index, k);
+    if !buffer.len().is_multiple_of(entry_size) {
+        return Err(StorageError::DeserializationError.into());
+    }
+    Ok(buffer
+        .chunks(entry_size)
+        .map(|chunk| match table_index {
+            1 => CompressedEntry {
+                x: Some(read_uint(chunk) as u64),
+                fx: None,
+                line_point: None,
+            },
+            7 => CompressedEntry {
+                x: None,
+                fx: Some(read_uint(&chunk[..x_size(k)]) as u64),
+                line_point: Some(read_uint(&chunk[x_size(k)..])),
+            },
+            _ => CompressedEntry {
+                x: None,
+                fx: None,
+                line_point: Some(read_uint(chunk)),
+            },
+        })
+        .collect())
+}
+
+/// Moves a file, copying it when the destination is on another disk
+///
+/// The copy is written next to the destination first so that a partial
+/// file never appears under the final name.
+pub fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let mut part_path = to.as_os_str().to_owned();
+    part_path.push(".part");
+    copy(from, &part_path).context(format!("Could not copy {:?} to {:?}", from, part_path))?;
+    rename(&part_path, to).context(format!("Could not rename {:?} to {:?}", part_path, to))?;
+    remove_file(from).context(format!("Could not remove {:?}", from))?;
+    Ok(())
 }
 
 pub struct ChunkReader {
+    pub table_index: usize,
+    pub k: usize,
     pub remaining_size: usize,
     pub entry_size: usize,
+    pub entries_per_chunk: usize,
+    pub entries_count: usize,
     pub file: File,
 }
 
 impl ChunkReader {
-    pub fn new(path: &Path, table_index: usize, k: usize) -> Result<Self> {
+    pub fn new(path: &Path, table_index: usize, k: usize, config: &PlottingConfig) -> Result<Self> {
         let file =
             File::open(path.join(format!(table_final_filename_format!(), table_index))).context(
                 format!("Cannot open final plot file for table {}", table_index),
             )?;
+        Self::from_file(file, table_index, k, config)
+    }
+
+    /// Reads any file of entries of the table, such as a bucket file
+    pub fn from_file(
+        file: File,
+        table_index: usize,
+        k: usize,
+        config: &PlottingConfig,
+    ) -> Result<Self> {
         let file_size = file.metadata()?.len() as usize;
         let entry_size = plotentry_size(table_index, k);
-        let remaining_size = file_size;
 
-        if file_size % entry_size != 0 {
+        if !file_size.is_multiple_of(entry_size) {
             return Err(StorageError::InvalidFileContent.into());
         }
 
         Ok(ChunkReader {
+            table_index,
+            k,
             file,
             entry_size,
-            remaining_size,
+            entries_per_chunk: config.entries_per_chunk(entry_size),
+            remaining_size: file_size,
+            entries_count: file_size / entry_size,
         })
     }
 
@@ -148,27 +389,40 @@ impl ChunkReader {
             return Err(StorageError::EndOfFile.into());
         }
         let mut buffer;
-        if self.remaining_size > *ENTRIES_PER_CHUNK * self.entry_size {
-            buffer = vec![0; *ENTRIES_PER_CHUNK * self.entry_size];
+        let chunk_size = self.entries_per_chunk * self.entry_size;
+        if self.remaining_size > chunk_size {
+            buffer = vec![0; chunk_size];
             self.file.read_exact(&mut buffer)?;
-            self.remaining_size -= *ENTRIES_PER_CHUNK * self.entry_size;
+            self.remaining_size -= chunk_size;
         } else {
             buffer = Vec::new();
             let amount = self.file.read_to_end(&mut buffer)?;
             self.remaining_size -= amount;
         }
-        let entries: Vec<PlotEntry> = deserialize(&buffer, self.entry_size)?;
+        let entries: Vec<PlotEntry> = deserialize(&buffer, self.table_index, self.k)?;
         Ok(entries)
     }
 }
 
+/// Reads the table chunk by chunk until the end of the file
+impl Iterator for ChunkReader {
+    type Item = Result<Vec<PlotEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_size == 0 {
+            return None;
+        }
+        Some(self.read_chunk())
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use tempdir::TempDir;
 
     use super::*;
-    use crate::bits::to_bits;
+    use crate::bits::to_raw_bits;
     use std::io::Read;
 
     #[test]
@@ -178,28 +432,111 @@ mod tests {
         let test_data = vec![
             PlotEntry {
                 fx: 2,
-                metadata: Some(to_bits(3, test_k).as_raw_slice().to_vec()),
+                metadata: Some(3),
                 position: None,
                 offset: None,
             },
             PlotEntry {
                 fx: 6,
-                metadata: Some(to_bits(1, test_k).as_raw_slice().to_vec()),
+                metadata: Some(1),
                 position: None,
                 offset: None,
             },
         ];
         let path = dir.path().join("store_table_1");
-        store_table_part(&test_data, &path).unwrap();
+        store_table_part(&test_data, &path, 1, test_k).unwrap();
 
         let mut verify_buffer = Vec::new();
         File::open(&path)
             .unwrap()
             .read_to_end(&mut verify_buffer)
             .unwrap();
-        let verify_data: Vec<PlotEntry> = deserialize(&verify_buffer, plotentry_size(1, test_k))?;
+        let verify_data: Vec<PlotEntry> = deserialize(&verify_buffer, 1, test_k)?;
 
         assert_eq!(test_data, verify_data);
         Ok(())
     }
+
+    #[test]
+    fn test_serialize_all_tables() {
+        let test_k = 20;
+        for table_index in 1..=7 {
+            let metadata_size = metadata_size_bits(table_index, test_k);
+            let entries = (0..50u64)
+                .map(|i| PlotEntry {
+                    fx: (i * 104_729) % (1 << (test_k + PARAM_EXT)),
+                    metadata: Some(
+                        (i as u128 * 0x9e37_79b9_7f4a_7c15_f39c) & ((1 << metadata_size) - 1),
+                    ),
+                    position: (table_index > 1).then(|| i * 1000),
+                    offset: (table_index > 1).then(|| (1 << (test_k + 1)) - 1 - i),
+                })
+                .collect::<Vec<PlotEntry>>();
+
+            let bin_data = serialize(&entries, table_index, test_k).unwrap();
+            assert_eq!(bin_data.len(), 50 * plotentry_size(table_index, test_k));
+            assert_eq!(
+                deserialize(&bin_data, table_index, test_k).unwrap(),
+                entries
+            );
+        }
+    }
+
+    #[test]
+    fn test_metadata_layout() {
+        for size in [12, 40, 64, 80, 128] {
+            for value in [0, 1, 0xabcd, u128::MAX] {
+                let value = if size < 128 {
+                    value & ((1 << size) - 1)
+                } else {
+                    value
+                };
+                let mut buffer = vec![0; 24];
+                write_metadata(&mut buffer, 5, value, size);
+                assert_eq!(read_metadata(&buffer, 5, size), value);
+
+                // Same bits as the raw bytes of the bit vector of the metadata
+                let mut expected = vec![0; 24];
+                for (i, byte) in to_raw_bits(value, size).iter().enumerate() {
+                    write_bits(
+                        &mut expected,
+                        5 + i * 8,
+                        *byte as u64,
+                        (size - i * 8).min(8),
+                    );
+                }
+                assert_eq!(buffer, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_plotentry_size() {
+        // fx (k + 6 bits) and x (k bits)
+        assert_eq!(plotentry_size(1, 20), 6);
+        // fx, 4k bits of metadata, position and offset (k + 1 bits each)
+        assert_eq!(plotentry_size(3, 20), 19);
+        // fx, position and offset
+        assert_eq!(plotentry_size(7, 20), 9);
+    }
+
+    #[test]
+    fn test_serialize_value_too_large() {
+        let test_k = 12;
+        let entry = PlotEntry {
+            fx: 1,
+            metadata: Some(0),
+            position: Some(1 << (test_k + 1)),
+            offset: Some(1),
+        };
+        assert!(serialize(&[entry], 7, test_k).is_err());
+
+        let entry = PlotEntry {
+            fx: 1,
+            metadata: Some(1 << test_k),
+            position: None,
+            offset: None,
+        };
+        assert!(serialize(&[entry], 1, test_k).is_err());
+    }
 }
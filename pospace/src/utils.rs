@@ -15,3 +15,54 @@ pub fn b_id(x: u64) -> u64 {
 pub fn c_id(x: u64) -> u64 {
     divmod(x % PARAM_BC, PARAM_C).1
 }
+
+/// Encodes two positions into a single line point, `x` must be greater than `y`
+pub fn square_to_line_point(x: u64, y: u64) -> u128 {
+    let x = x as u128;
+    x * (x - 1) / 2 + y as u128
+}
+
+/// Decodes a line point into the two positions `(x, y)` with `x > y`
+pub fn line_point_to_square(line_point: u128) -> (u64, u64) {
+    // Approximation using floats, corrected below for large line points
+    let mut x = (((line_point as f64 * 8.0 + 1.0).sqrt() + 1.0) / 2.0) as u128;
+    x = x.max(1);
+    while x * (x - 1) / 2 > line_point {
+        x -= 1;
+    }
+    while (x + 1) * x / 2 <= line_point {
+        x += 1;
+    }
+    (x as u64, (line_point - x * (x - 1) / 2) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_line_point_small() {
+        assert_eq!(square_to_line_point(1, 0), 0);
+        assert_eq!(square_to_line_point(2, 0), 1);
+        assert_eq!(square_to_line_point(2, 1), 2);
+        assert_eq!(line_point_to_square(0), (1, 0));
+        assert_eq!(line_point_to_square(1), (2, 0));
+        assert_eq!(line_point_to_square(2), (2, 1));
+    }
+
+    #[test]
+    fn test_line_point_roundtrip() {
+        let values = [
+            (3, 2),
+            (1000, 1),
+            (1 << 20, (1 << 20) - 1),
+            ((1 << 51) - 1, 12345),
+            ((1 << 51) - 1, (1 << 51) - 2),
+        ];
+        for (x, y) in values.iter() {
+            let line_point = square_to_line_point(*x, *y);
+            assert_eq!(line_point_to_square(line_point), (*x, *y));
+        }
+    }
+}
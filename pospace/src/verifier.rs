@@ -1,83 +1,147 @@
-use crate::bits::{from_bits, to_bits, BitsWrapper};
+use crate::bits::{from_bits, from_raw_bits, BitsWrapper};
+use crate::constants::{MAX_K, MIN_K, PARAM_EXT, PARAM_PLOT_FILTER};
+use crate::core::{PlotSeed, NUMBER_OF_TABLES};
 use crate::error::VerifierError;
 use crate::f1_calculator::F1Calculator;
 use crate::fx_calculator::FxCalculator;
-use crate::proofs::Proof;
-use crate::storage::PlotEntry;
-use anyhow::{Context, Result};
-use bitvec::order::Lsb0;
-use bitvec::view::BitView;
+use crate::proofs::{passes_plot_filter, quality_index, quality_string, Proof, QualityString};
+use anyhow::Result;
+use rayon::prelude::*;
 
-pub struct Verifier {}
+/// Number of x-values of a proof, two per entry of table 1 leading to an entry of table 7
+pub const PROOF_X_VALUES: usize = 1 << (NUMBER_OF_TABLES - 1);
+/// Size in bytes of a challenge
+pub const CHALLENGE_SIZE: usize = 32;
+
+pub struct Verifier {
+    plot_filter: usize,
+}
 
 impl Verifier {
     pub fn new() -> Self {
-        Verifier {}
+        Verifier {
+            plot_filter: PARAM_PLOT_FILTER,
+        }
     }
 
-    pub fn get_quality_string(&self) {
-        todo!()
+    /// Changes the number of zero bits required by the plot filter
+    pub fn with_plot_filter(mut self, filter_bits: usize) -> Self {
+        self.plot_filter = filter_bits;
+        self
     }
 
-    pub fn verify_proof(&self, proof: &Proof) -> Result<()> {
-        let f1_calculator = F1Calculator::new(proof.k, proof.plot_seed);
+    /// Verifies the proof and returns its quality string, the same one as the prover's
+    pub fn get_quality_string(&self, proof: &Proof) -> Result<QualityString> {
+        self.verify_proof(proof)?;
+        let index = 2 * quality_index(&proof.challenge);
+        Ok(quality_string(
+            &proof.challenge,
+            &[proof.x_values[index], proof.x_values[index + 1]],
+        ))
+    }
 
-        let mut fx_values = Vec::new();
-        let mut metadata = Vec::new();
+    /// Checks the structure of the proof, then that it passes the plot filter and
+    /// that its x-values, in the canonical order, lead to the challenge
+    ///
+    /// Any proof can be given, an invalid one is rejected without panicking.
+    pub fn verify_proof(&self, proof: &Proof) -> Result<()> {
+        validate_structure(proof)?;
 
-        if proof.x_values.len() != 64 {
-            return Err(VerifierError::InvalidXValuesCount.into());
+        if !passes_plot_filter(
+            &proof.plot_id,
+            &proof.challenge,
+            &proof.signage_data,
+            self.plot_filter,
+        ) {
+            return Err(VerifierError::PlotFilterNotPassed.into());
         }
 
-        for x in &proof.x_values {
-            let fx = f1_calculator.calculate_f1(&BitsWrapper::from(*x, proof.k))?;
-            fx_values.push(fx);
-            metadata.push(to_bits(*x, proof.k));
+        let mut entries = table1_entries(proof.k, proof.plot_id.plot_seed(), &proof.x_values)?;
+        for table_index in 2..=NUMBER_OF_TABLES {
+            let fx_calculator = FxCalculator::new(proof.k, table_index);
+            entries = entries
+                .chunks(2)
+                .map(|pair| {
+                    let (left, right) = (pair[0], pair[1]);
+                    if left.0 >= right.0 {
+                        return Err(VerifierError::NotCanonical(table_index).into());
+                    }
+                    if !fx_calculator.is_match(left.0, right.0) {
+                        return Err(VerifierError::NotMatching(table_index).into());
+                    }
+                    Ok(fx_calculator.calculate_fn_int(left.0, left.1, right.1))
+                })
+                .collect::<Result<Vec<(u64, u128)>>>()?;
         }
 
-        for table_index in 2..8 {
-            let mut fx_calculator = FxCalculator::new(proof.k, table_index);
-            let mut temp_fx_values = Vec::new();
-            let mut temp_metadata = Vec::new();
+        if entries[0].0 >> PARAM_EXT != from_raw_bits(&proof.challenge, proof.k) as u64 {
+            return Err(VerifierError::ChallengeMismatch.into());
+        }
+        Ok(())
+    }
 
-            for i in (0..(1 << (8 - table_index))).step_by(2) {
-                let left_entry = PlotEntry {
-                    fx: from_bits(&fx_values[i]),
-                    metadata: None,
-                    position: None,
-                    offset: None,
-                };
-                let right_entry = PlotEntry {
-                    fx: from_bits(&fx_values[i + 1]),
-                    metadata: None,
-                    position: None,
-                    offset: None,
-                };
-                let left_bucket = vec![left_entry];
-                let right_bucket = vec![right_entry];
+    /// Verifies the proofs in parallel, the result of each proof being at the
+    /// same index as the proof
+    pub fn verify_batch(&self, proofs: &[Proof]) -> Vec<Result<()>> {
+        proofs
+            .par_iter()
+            .map(|proof| self.verify_proof(proof))
+            .collect()
+    }
+}
 
-                if fx_calculator
-                    .find_matches(&left_bucket, &right_bucket)
-                    .len()
-                    != 1
-                {
-                    return Result::<()>::Err(VerifierError::InvalidProof.into())
-                        .context("Invalid matches");
+/// Puts the x-values of a proof in the canonical order, the only one accepted by
+/// the verifier
+///
+/// At each level, the subtree whose entry has the smaller f-value comes first.
+/// The x-values must be the 64 x-values of a proof, each fitting in k bits.
+pub fn canonical_x_values(k: usize, plot_seed: PlotSeed, x_values: &[u64]) -> Result<Vec<u64>> {
+    let mut x_values = x_values.to_vec();
+    let mut entries = table1_entries(k, plot_seed, &x_values)?;
+    for table_index in 2..=NUMBER_OF_TABLES {
+        // Number of x-values below an entry of the previous table
+        let subtree_size = 1 << (table_index - 2);
+        let fx_calculator = FxCalculator::new(k, table_index);
+        entries = entries
+            .chunks_mut(2)
+            .zip(x_values.chunks_mut(2 * subtree_size))
+            .map(|(pair, subtrees)| {
+                if pair[0].0 > pair[1].0 {
+                    pair.swap(0, 1);
+                    subtrees.rotate_left(subtree_size);
                 }
+                fx_calculator.calculate_fn_int(pair[0].0, pair[0].1, pair[1].1)
+            })
+            .collect();
+    }
+    Ok(x_values)
+}
 
-                let res = fx_calculator.calculate_fn(&fx_values[i], &metadata[i], &metadata[i + 1]);
-                temp_fx_values.push(res.0);
-                temp_metadata.push(res.1);
-            }
-            fx_values = temp_fx_values;
-            metadata = temp_metadata;
-        }
+/// f-values and metadata of the entries of table 1 of the x-values
+fn table1_entries(k: usize, plot_seed: PlotSeed, x_values: &[u64]) -> Result<Vec<(u64, u128)>> {
+    let f1_calculator = F1Calculator::new(k, plot_seed);
+    x_values
+        .iter()
+        .map(|x| {
+            let fx = f1_calculator.calculate_f1(&BitsWrapper::from(*x, k))?;
+            Ok((from_bits(&fx), *x as u128))
+        })
+        .collect()
+}
 
-        if fx_values[0][0..proof.k] == proof.challenge.view_bits::<Lsb0>()[0..proof.k] {
-            return Ok(());
-        } else {
-            return Result::<()>::Err(VerifierError::InvalidProof.into())
-                .context("Proof does not match with the challenge");
-        }
+/// Checks the parameters of the proof before anything is computed from them
+fn validate_structure(proof: &Proof) -> Result<()> {
+    if !(MIN_K..=MAX_K).contains(&proof.k) {
+        return Err(VerifierError::InvalidK(proof.k).into());
+    }
+    if proof.challenge.len() != CHALLENGE_SIZE {
+        return Err(VerifierError::InvalidChallengeLength(proof.challenge.len()).into());
+    }
+    if proof.x_values.len() != PROOF_X_VALUES {
+        return Err(VerifierError::InvalidXValuesCount(proof.x_values.len()).into());
+    }
+    if let Some(x) = proof.x_values.iter().find(|x| **x >> proof.k != 0) {
+        return Err(VerifierError::XValueOutOfRange(*x).into());
     }
+    Ok(())
 }
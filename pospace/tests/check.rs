@@ -0,0 +1,86 @@
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
+use spaceframe_pospace::core::PoSpace;
+use spaceframe_pospace::plot::PlotFile;
+use spaceframe_pospace::plot_id::PlotId;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use tempdir::TempDir;
+
+fn test_plot_id() -> PlotId {
+    let farmer_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+        .unwrap()
+        .public_key();
+    let pool_key = Ed25519PrivateKey::from_bytes(&[2; 32])
+        .unwrap()
+        .public_key();
+    PlotId::new(farmer_key, pool_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+}
+
+/// Overwrites `size` bytes of the file from `offset` with `byte`
+fn overwrite(path: &Path, offset: u64, size: u64, byte: u8) {
+    let mut file = File::options().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&vec![byte; size as usize]).unwrap();
+}
+
+#[test]
+fn test_check_plot() {
+    let dir = TempDir::new("spaceframe_check").unwrap();
+    const TEST_K: usize = 14;
+    let pos = PoSpace::new(TEST_K, test_plot_id(), dir.path()).unwrap();
+    pos.plot().unwrap();
+
+    let report = pos.check(200).unwrap();
+    assert!(report.is_healthy());
+    assert_eq!(report.challenges, 200);
+    assert!(report.table_entries.iter().all(|&entries| entries > 0));
+    let ratio = report.proofs_per_challenge() / report.expected_proofs_per_challenge();
+    assert!(ratio > 0.5 && ratio < 1.5, "{:?}", report);
+
+    // Overwrites the x values of table 1, every proof becomes invalid
+    let header = PlotFile::open(pos.plot_path()).unwrap().header;
+    overwrite(
+        pos.plot_path(),
+        header.table_offsets[0],
+        header.table_sizes[0],
+        0x55,
+    );
+
+    let report = pos.check(200).unwrap();
+    assert!(!report.is_healthy());
+    assert!(report.invalid_proofs > 0);
+
+    // A truncated plot is rejected before any challenge
+    let size = pos.plot_path().metadata().unwrap().len();
+    File::options()
+        .write(true)
+        .open(pos.plot_path())
+        .unwrap()
+        .set_len(size - 1)
+        .unwrap();
+    assert!(pos.check(100).is_err());
+}
+
+#[test]
+fn test_check_plot_without_proofs() {
+    let dir = TempDir::new("spaceframe_check_table7").unwrap();
+    const TEST_K: usize = 14;
+    let pos = PoSpace::new(TEST_K, test_plot_id(), dir.path()).unwrap();
+    pos.plot().unwrap();
+
+    // Table 7 keeps its size but none of its entries can be found anymore
+    let header = PlotFile::open(pos.plot_path()).unwrap().header;
+    overwrite(
+        pos.plot_path(),
+        header.table_offsets[6],
+        header.table_sizes[6],
+        0,
+    );
+
+    let report = pos.check(200).unwrap();
+    assert_eq!(report.invalid_proofs, 0, "{:?}", report);
+    assert!(!report.has_expected_proofs(), "{:?}", report);
+    assert!(!report.is_healthy());
+}
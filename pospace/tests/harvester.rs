@@ -0,0 +1,80 @@
+use rand::{thread_rng, RngCore};
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
+use spaceframe_pospace::core::PoSpace;
+use spaceframe_pospace::harvester::Harvester;
+use spaceframe_pospace::plot_id::PlotId;
+use spaceframe_pospace::verifier::Verifier;
+use std::fs::{copy, remove_file, rename};
+use tempdir::TempDir;
+
+fn test_plot_id() -> PlotId {
+    let farmer_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+        .unwrap()
+        .public_key();
+    let pool_key = Ed25519PrivateKey::from_bytes(&[2; 32])
+        .unwrap()
+        .public_key();
+    PlotId::new(farmer_key, pool_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+}
+
+#[test]
+fn test_harvester() {
+    let first_dir = TempDir::new("spaceframe_harvester_1").unwrap();
+    let second_dir = TempDir::new("spaceframe_harvester_2").unwrap();
+    const TEST_K: usize = 14;
+    let pos = PoSpace::new(TEST_K, test_plot_id(), first_dir.path()).unwrap();
+    pos.plot().unwrap();
+    let copy_path = second_dir.path().join("copy.plot");
+    copy(pos.plot_path(), &copy_path).unwrap();
+
+    let harvester = Harvester::new(&[first_dir.path().to_owned()]).with_disk_concurrency(1);
+    assert_eq!(harvester.refresh().unwrap(), (1, 0));
+    harvester.add_directory(second_dir.path());
+    assert_eq!(harvester.refresh().unwrap(), (1, 0));
+    assert_eq!(harvester.refresh().unwrap(), (0, 0));
+    assert_eq!(harvester.plots().len(), 2);
+
+    let mut challenge = [0u8; 32];
+    let mut signage_data = [0u8; 32];
+    let qualities = loop {
+        thread_rng().fill_bytes(&mut challenge);
+        thread_rng().fill_bytes(&mut signage_data);
+        let qualities = harvester.get_qualities(&challenge, &signage_data);
+        if !qualities.is_empty() {
+            break qualities;
+        }
+    };
+    // Both plots have the same content
+    assert_eq!(qualities.len() % 2, 0);
+
+    let verifier = Verifier::new();
+    for quality in &qualities {
+        let proof = harvester
+            .get_proof(&quality.plot_path, &challenge, &signage_data, quality.index)
+            .unwrap();
+        assert_eq!(
+            verifier.get_quality_string(&proof).unwrap(),
+            quality.quality
+        );
+    }
+    assert!(harvester
+        .get_proof(&copy_path, &challenge, &signage_data, qualities.len())
+        .is_err());
+
+    // Plots moved or removed while harvesting
+    let moved_path = second_dir.path().join("moved.plot");
+    rename(&copy_path, &moved_path).unwrap();
+    assert_eq!(harvester.refresh().unwrap(), (1, 1));
+    remove_file(&moved_path).unwrap();
+    assert_eq!(harvester.refresh().unwrap(), (0, 1));
+    assert!(harvester
+        .get_proof(&moved_path, &challenge, &signage_data, qualities[0].index)
+        .is_err());
+
+    harvester.remove_directory(first_dir.path());
+    assert_eq!(harvester.refresh().unwrap(), (0, 1));
+    assert!(harvester
+        .get_qualities(&challenge, &signage_data)
+        .is_empty());
+}
@@ -1,8 +1,27 @@
 use log::LevelFilter;
 use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
+use spaceframe_pospace::config::PlottingConfig;
 use spaceframe_pospace::core::PoSpace;
+use spaceframe_pospace::error::PoSpaceError;
+use spaceframe_pospace::plot::PlotFile;
+use spaceframe_pospace::plot_id::PlotId;
+use spaceframe_pospace::progress::{CancellationToken, ProgressSink};
+use std::fs::read_dir;
+use std::sync::{Arc, Mutex};
 use tempdir::TempDir;
 
+fn test_plot_id() -> PlotId {
+    let farmer_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+        .unwrap()
+        .public_key();
+    let pool_key = Ed25519PrivateKey::from_bytes(&[2; 32])
+        .unwrap()
+        .public_key();
+    PlotId::new(farmer_key, pool_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+}
+
 #[test]
 fn test_plotting() {
     TermLogger::init(
@@ -15,7 +34,143 @@ fn test_plotting() {
 
     let dir = TempDir::new("spaceframe_plotting").unwrap();
     const TEST_K: usize = 14;
-    let plot_seed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
-    let pos = PoSpace::new(TEST_K, plot_seed, dir.path()).unwrap();
-    pos.run_phase_1().unwrap();
+    let plot_id = test_plot_id();
+    let pos = PoSpace::new(TEST_K, plot_id, dir.path()).unwrap();
+    pos.plot().unwrap();
+}
+
+#[test]
+fn test_plotting_with_directories() {
+    let tmp_dir = TempDir::new("spaceframe_plotting_tmp").unwrap();
+    let tmp2_dir = TempDir::new("spaceframe_plotting_tmp2").unwrap();
+    let final_dir = TempDir::new("spaceframe_plotting_final").unwrap();
+    const TEST_K: usize = 14;
+    let plot_id = test_plot_id();
+    let pos = PoSpace::new(TEST_K, plot_id, tmp_dir.path())
+        .unwrap()
+        .with_directories(
+            tmp_dir.path(),
+            Some(tmp2_dir.path()),
+            &final_dir.path().join("plots"),
+        );
+    pos.plot().unwrap();
+
+    assert_eq!(read_dir(tmp_dir.path()).unwrap().count(), 0);
+    assert_eq!(read_dir(tmp2_dir.path()).unwrap().count(), 0);
+    assert_eq!(read_dir(final_dir.path().join("plots")).unwrap().count(), 1);
+    assert!(pos.plot_path().starts_with(final_dir.path()));
+
+    let opened = PoSpace::open(pos.plot_path()).unwrap();
+    assert_eq!(opened.k, TEST_K);
+}
+
+#[test]
+fn test_plotting_with_small_memory_budget() {
+    const TEST_K: usize = 14;
+    let plot_id = test_plot_id();
+
+    let dir = TempDir::new("spaceframe_plotting").unwrap();
+    let pos = PoSpace::new(TEST_K, plot_id, dir.path()).unwrap();
+    pos.plot().unwrap();
+
+    // Forces the buckets to be flushed to disk many times, with a single thread
+    let small_dir = TempDir::new("spaceframe_plotting_small").unwrap();
+    let config = PlottingConfig::new(256 * 1024, 1, 128).unwrap();
+    let small_pos = PoSpace::new(TEST_K, plot_id, small_dir.path())
+        .unwrap()
+        .with_config(config);
+    small_pos.plot().unwrap();
+
+    let header = PlotFile::open(pos.plot_path()).unwrap().header;
+    let small_header = PlotFile::open(small_pos.plot_path()).unwrap().header;
+    assert_eq!(header.table_sizes, small_header.table_sizes);
+}
+
+#[test]
+fn test_plotting_with_several_threads() {
+    const TEST_K: usize = 14;
+    let plot_id = test_plot_id();
+
+    let single_dir = TempDir::new("spaceframe_plotting_single").unwrap();
+    let single_pos = PoSpace::new(TEST_K, plot_id, single_dir.path())
+        .unwrap()
+        .with_config(PlottingConfig::new(1 << 30, 1, 128).unwrap());
+    single_pos.plot().unwrap();
+
+    let multi_dir = TempDir::new("spaceframe_plotting_multi").unwrap();
+    let multi_pos = PoSpace::new(TEST_K, plot_id, multi_dir.path())
+        .unwrap()
+        .with_config(PlottingConfig::new(1 << 30, 4, 128).unwrap());
+    multi_pos.plot().unwrap();
+
+    let single_header = PlotFile::open(single_pos.plot_path()).unwrap().header;
+    let multi_header = PlotFile::open(multi_pos.plot_path()).unwrap().header;
+    assert_eq!(single_header.table_sizes, multi_header.table_sizes);
+}
+
+#[derive(Default)]
+struct RecordingSink {
+    cancellation: CancellationToken,
+    tables: Mutex<Vec<(usize, usize)>>,
+    progress: Mutex<Vec<(f64, u64)>>,
+}
+
+impl ProgressSink for RecordingSink {
+    fn table_started(&self, phase: usize, table_index: usize) {
+        if phase == 1 && table_index == 3 {
+            self.cancellation.cancel();
+        }
+    }
+
+    fn table_finished(&self, phase: usize, table_index: usize) {
+        self.tables.lock().unwrap().push((phase, table_index));
+    }
+
+    fn progress(&self, _phase: usize, percentage: f64, bytes_written: u64) {
+        self.progress
+            .lock()
+            .unwrap()
+            .push((percentage, bytes_written));
+    }
+}
+
+#[test]
+fn test_plotting_progress() {
+    const TEST_K: usize = 14;
+    let dir = TempDir::new("spaceframe_plotting").unwrap();
+    let sink = Arc::new(RecordingSink::default());
+    let pos = PoSpace::new(TEST_K, test_plot_id(), dir.path())
+        .unwrap()
+        .with_progress(sink.clone());
+    pos.plot().unwrap();
+
+    assert_eq!(sink.tables.lock().unwrap().len(), 20);
+    let progress = sink.progress.lock().unwrap();
+    assert!(progress
+        .windows(2)
+        .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1));
+    assert_eq!(progress.last().unwrap().0, 100.0);
+    assert!(progress.last().unwrap().1 >= pos.plot_path().metadata().unwrap().len());
+}
+
+#[test]
+fn test_plotting_cancelled() {
+    const TEST_K: usize = 14;
+    let tmp_dir = TempDir::new("spaceframe_plotting_tmp").unwrap();
+    let final_dir = TempDir::new("spaceframe_plotting_final").unwrap();
+    let sink = Arc::new(RecordingSink::default());
+    let pos = PoSpace::new(TEST_K, test_plot_id(), tmp_dir.path())
+        .unwrap()
+        .with_directories(tmp_dir.path(), None, final_dir.path())
+        .with_progress(sink.clone())
+        .with_cancellation(sink.cancellation.clone());
+
+    let err = pos.plot().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<PoSpaceError>(),
+        Some(PoSpaceError::Cancelled)
+    ));
+    assert_eq!(*sink.tables.lock().unwrap(), vec![(1, 1), (1, 2)]);
+    assert_eq!(read_dir(tmp_dir.path()).unwrap().count(), 0);
+    assert_eq!(read_dir(final_dir.path()).unwrap().count(), 0);
 }
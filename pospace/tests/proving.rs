@@ -1,10 +1,23 @@
 use log::LevelFilter;
 use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
 use spaceframe_pospace::core::PoSpace;
+use spaceframe_pospace::plot_id::PlotId;
 use spaceframe_pospace::proofs::Prover;
 use spaceframe_pospace::verifier::Verifier;
 use tempdir::TempDir;
 
+fn test_plot_id() -> PlotId {
+    let farmer_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+        .unwrap()
+        .public_key();
+    let pool_key = Ed25519PrivateKey::from_bytes(&[2; 32])
+        .unwrap()
+        .public_key();
+    PlotId::new(farmer_key, pool_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+}
+
 #[test]
 fn test_proving() {
     TermLogger::init(
@@ -17,18 +30,75 @@ fn test_proving() {
 
     let dir = TempDir::new("spaceframe_proving").unwrap();
     const TEST_K: usize = 14;
-    let plot_seed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
+    let plot_id = test_plot_id();
     let challenge = [
-        180, 152, 16, 199, 88, 233, 76, 61, 6, 3, 95, 26, 98, 214, 224, 127, 19, 87, 188, 143, 134,
-        79, 228, 168, 126, 117, 83, 103, 121, 41, 79, 94,
+        214, 62, 164, 15, 31, 112, 14, 70, 65, 39, 93, 181, 223, 148, 191, 75, 38, 140, 174, 19,
+        47, 143, 170, 95, 207, 147, 82, 60, 164, 149, 253, 77,
     ];
-    let pos = PoSpace::new(TEST_K, plot_seed, dir.path()).unwrap();
-    pos.run_phase_1().unwrap();
+    let signage_data = [
+        71, 89, 141, 51, 210, 74, 164, 219, 171, 225, 170, 45, 48, 241, 207, 80, 163, 181, 11, 222,
+        33, 80, 98, 74, 46, 34, 229, 236, 166, 175, 52, 80,
+    ];
+    let pos = PoSpace::new(TEST_K, plot_id, dir.path()).unwrap();
+    pos.plot().unwrap();
+
+    // The plot must be usable from its file alone
+    let pos = PoSpace::open(pos.plot_path()).unwrap();
+    assert_eq!(pos.k, TEST_K);
+    assert_eq!(pos.plot_id, plot_id);
 
     let prover = Prover::new(pos);
-    let proofs = prover.retrieve_all_proofs(challenge.as_ref()).unwrap();
+    let proofs = prover
+        .retrieve_all_proofs(&challenge, &signage_data)
+        .unwrap();
     assert_eq!(proofs.len(), 1);
 
     let verifier = Verifier::new();
     assert!(verifier.verify_proof(&proofs[0]).is_ok(), "Invalid proof");
+
+    // The plot does not pass the filter with other signage data
+    let other_signage_data = [0u8; 32];
+    assert!(!prover.passes_plot_filter(&challenge, &other_signage_data));
+    assert!(prover
+        .retrieve_all_proofs(&challenge, &other_signage_data)
+        .unwrap()
+        .is_empty());
+    let mut filtered_proof = proofs[0].clone();
+    filtered_proof.signage_data = other_signage_data.to_vec();
+    assert!(verifier.verify_proof(&filtered_proof).is_err());
+    assert!(Verifier::new()
+        .with_plot_filter(0)
+        .verify_proof(&filtered_proof)
+        .is_ok());
+
+    let qualities = prover
+        .get_quality_string(&challenge, &signage_data)
+        .unwrap();
+    assert_eq!(qualities.len(), 1);
+    assert_eq!(
+        verifier.get_quality_string(&proofs[0]).unwrap(),
+        qualities[0]
+    );
+
+    // The proof behind a quality string can be read alone
+    let full_proof = prover
+        .get_full_proof(&challenge, &signage_data, 0)
+        .unwrap()
+        .unwrap();
+    assert_eq!(full_proof, proofs[0]);
+    assert!(prover
+        .get_full_proof(&challenge, &signage_data, 1)
+        .unwrap()
+        .is_none());
+    assert!(prover
+        .get_full_proof(&challenge, &other_signage_data, 0)
+        .unwrap()
+        .is_none());
+
+    let mut other_challenge = challenge;
+    other_challenge[31] ^= 0b11111;
+    let other_qualities = prover
+        .get_quality_string(&other_challenge, &signage_data)
+        .unwrap();
+    assert_ne!(other_qualities[0], qualities[0]);
 }
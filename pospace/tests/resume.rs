@@ -0,0 +1,93 @@
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
+use spaceframe_pospace::checkpoint::CheckpointManifest;
+use spaceframe_pospace::core::PoSpace;
+use spaceframe_pospace::plot_id::PlotId;
+use spaceframe_pospace::proofs::Prover;
+use spaceframe_pospace::verifier::Verifier;
+use std::fs::{remove_file, File};
+use std::io::Write;
+use tempdir::TempDir;
+
+fn test_plot_id() -> PlotId {
+    let farmer_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+        .unwrap()
+        .public_key();
+    let pool_key = Ed25519PrivateKey::from_bytes(&[2; 32])
+        .unwrap()
+        .public_key();
+    PlotId::new(farmer_key, pool_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+}
+
+#[test]
+fn test_resume_plotting() {
+    let dir = TempDir::new("spaceframe_resume").unwrap();
+    const TEST_K: usize = 14;
+    let plot_id = test_plot_id();
+    let challenge = [
+        214, 62, 164, 15, 31, 112, 14, 70, 65, 39, 93, 181, 223, 148, 191, 75, 38, 140, 174, 19,
+        47, 143, 170, 95, 207, 147, 82, 60, 164, 149, 253, 77,
+    ];
+    let signage_data = [
+        71, 89, 141, 51, 210, 74, 164, 219, 171, 225, 170, 45, 48, 241, 207, 80, 163, 181, 11, 222,
+        33, 80, 98, 74, 46, 34, 229, 236, 166, 175, 52, 80,
+    ];
+    let pos = PoSpace::new(TEST_K, plot_id, dir.path()).unwrap();
+    pos.run_phase_1().unwrap();
+
+    let table7_size = dir.path().join("table7_final").metadata().unwrap().len();
+    let table4_modified = dir
+        .path()
+        .join("table4_final")
+        .metadata()
+        .unwrap()
+        .modified()
+        .unwrap();
+
+    // Simulate an interruption while computing table 5
+    let mut manifest = CheckpointManifest::load(dir.path()).unwrap();
+    assert_eq!(manifest.tables.len(), 7);
+    manifest.tables.truncate(4);
+    manifest.save(dir.path()).unwrap();
+    for table_index in 5..=7 {
+        remove_file(dir.path().join(format!("table{}_final", table_index))).unwrap();
+    }
+    File::create(dir.path().join("table5_raw_1"))
+        .unwrap()
+        .write_all(b"partial content")
+        .unwrap();
+
+    assert_eq!(manifest.first_incomplete_table(dir.path()).unwrap(), 5);
+
+    pos.run_phase_1().unwrap();
+
+    assert_eq!(
+        dir.path()
+            .join("table4_final")
+            .metadata()
+            .unwrap()
+            .modified()
+            .unwrap(),
+        table4_modified,
+        "Table 4 must not be computed again"
+    );
+    assert_eq!(
+        dir.path().join("table7_final").metadata().unwrap().len(),
+        table7_size
+    );
+    assert!(!dir.path().join("table5_raw_1").exists());
+
+    pos.run_phase_2().unwrap();
+    pos.run_phase_3().unwrap();
+    pos.run_phase_4().unwrap();
+    assert!(CheckpointManifest::load(dir.path()).is_err());
+
+    let prover = Prover::new(pos);
+    let proofs = prover
+        .retrieve_all_proofs(&challenge, &signage_data)
+        .unwrap();
+    assert_eq!(proofs.len(), 1);
+
+    let verifier = Verifier::new();
+    assert!(verifier.verify_proof(&proofs[0]).is_ok(), "Invalid proof");
+}
@@ -1,71 +1,64 @@
 use rand::thread_rng;
 use rand::Rng;
-use spaceframe_pospace::bits::to_bits;
-use spaceframe_pospace::sort::sort_table_on_disk;
-use spaceframe_pospace::storage::plotentry_size;
-use spaceframe_pospace::storage::ENTRIES_PER_CHUNK;
-use spaceframe_pospace::storage::{store_table_part, PlotEntry};
+use spaceframe_pospace::config::PlottingConfig;
+use spaceframe_pospace::sort::{bucket_count, sort_table_on_disk, BucketWriter};
+use spaceframe_pospace::storage::PlotEntry;
+use spaceframe_pospace::storage::{deserialize, plotentry_size};
 use std::fs::File;
 use std::io::Read;
 use tempdir::TempDir;
 
-fn setup_storage() -> TempDir {
+fn setup_storage(config: &PlottingConfig) -> TempDir {
     let dir = TempDir::new("spaceframe_test_data").unwrap();
     let mut rng = thread_rng();
-    for i in 0..3 {
-        let data = (0..100)
-            .map(|x| {
-                return PlotEntry {
-                    fx: rng.gen_range(0..120),
-                    metadata: Some(to_bits(100 * i + x, 12).as_raw_slice().to_vec()),
-                    position: None,
-                    offset: None,
-                };
+    let buckets = bucket_count(1, 12, config);
+    let mut bucket_writer = BucketWriter::new(dir.path(), 1, 12, buckets, config);
+    for x in 0..300 {
+        bucket_writer
+            .push(PlotEntry {
+                fx: rng.gen_range(0..1 << 18),
+                metadata: Some(x),
+                position: None,
+                offset: None,
             })
-            .collect::<Vec<PlotEntry>>();
-        store_table_part(&data, &dir.path().join(format!("table1_raw_{}", i))).unwrap();
+            .unwrap();
     }
+    bucket_writer.finish().unwrap();
     dir
 }
 
 #[test]
-fn test_kway_merge_table1() {
-    let dir = setup_storage();
-    sort_table_on_disk(1, dir.path(), 10, 12).unwrap();
+fn test_bucket_sort_table1() {
+    let config = PlottingConfig::new(10 * plotentry_size(1, 12), 1, 16).unwrap();
+    let dir = setup_storage(&config);
+    sort_table_on_disk(1, dir.path(), &config, 12).unwrap();
     let mut file = File::open(dir.path().join("table1_final")).unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
-    let entries = buffer
-        .chunks(plotentry_size(1, 12))
-        .map(|chunk| {
-            return bincode::deserialize(&chunk).unwrap();
-        })
-        .collect::<Vec<PlotEntry>>();
+    let entries = deserialize(&buffer, 1, 12).unwrap();
     let mut last = entries[0].fx;
     assert_eq!(300, entries.len());
     for entry in entries {
         assert!(entry.fx >= last, "Final table not correctly sorted");
         last = entry.fx;
     }
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
 }
 
 #[test]
-fn test_kway_merge_table1_big_chunk() {
-    let dir = setup_storage();
-    sort_table_on_disk(1, dir.path(), *ENTRIES_PER_CHUNK, 12).unwrap();
+fn test_bucket_sort_table1_big_chunk() {
+    let config = PlottingConfig::default();
+    let dir = setup_storage(&config);
+    sort_table_on_disk(1, dir.path(), &config, 12).unwrap();
     let mut file = File::open(dir.path().join("table1_final")).unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
-    let entries = buffer
-        .chunks(plotentry_size(1, 12))
-        .map(|chunk| {
-            return bincode::deserialize(&chunk).unwrap();
-        })
-        .collect::<Vec<PlotEntry>>();
+    let entries = deserialize(&buffer, 1, 12).unwrap();
     let mut last = entries[0].fx;
     assert_eq!(300, entries.len());
     for entry in entries {
         assert!(entry.fx >= last, "Final table not correctly sorted");
         last = entry.fx;
     }
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
 }
@@ -0,0 +1,343 @@
+//! Checks the implementation against the test vectors of `tests/vectors`
+//!
+//! The vectors fix the values of f1, fx, the matches and the proofs of plots,
+//! any change of them invalidates the existing plots. They are only generated
+//! again on purpose with `cargo test --test vectors -- --ignored`.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
+use spaceframe_pospace::bits::{from_bits, from_bits_u128, to_bits, to_bits_u128, BitsWrapper};
+use spaceframe_pospace::constants::{PARAM_BC, PARAM_EXT};
+use spaceframe_pospace::core::{collation_size_bits, PlotSeed, PoSpace};
+use spaceframe_pospace::f1_calculator::F1Calculator;
+use spaceframe_pospace::fx_calculator::FxCalculator;
+use spaceframe_pospace::plot_id::PlotId;
+use spaceframe_pospace::proofs::{Proof, Prover};
+use spaceframe_pospace::storage::PlotEntry;
+use spaceframe_pospace::verifier::Verifier;
+use std::convert::TryInto;
+use std::fs::File;
+use std::path::PathBuf;
+use tempdir::TempDir;
+
+const PLOT_SEED: PlotSeed = *b"aaaabbbbccccddddaaaabbbbccccdddd";
+const PROOF_K: [usize; 3] = [12, 13, 14];
+const CHALLENGES_PER_PLOT: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+struct F1Vector {
+    k: usize,
+    plot_seed: PlotSeed,
+    x: u64,
+    f1: u64,
+}
+
+/// The metadata are hexadecimal strings, they can hold more than 64 bits
+#[derive(Serialize, Deserialize)]
+struct FxVector {
+    k: usize,
+    table_index: usize,
+    y: u64,
+    left: String,
+    right: String,
+    fx: u64,
+    metadata: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MatchingVector {
+    left: Vec<u64>,
+    right: Vec<u64>,
+    /// Indexes in the left and right buckets
+    matches: Vec<(usize, usize)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProofVector {
+    x_values: Vec<u64>,
+    quality: Vec<u8>,
+}
+
+/// Proofs of a plot for a challenge, with the plot filter disabled
+#[derive(Serialize, Deserialize)]
+struct PlotVector {
+    k: usize,
+    /// Serialized plot id
+    plot_id: Vec<u8>,
+    challenge: Vec<u8>,
+    /// Sorted by x values
+    proofs: Vec<ProofVector>,
+}
+
+fn vector_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/vectors")
+        .join(name)
+}
+
+fn load<T: for<'de> Deserialize<'de>>(name: &str) -> Vec<T> {
+    let file = File::open(vector_path(name)).unwrap();
+    serde_json::from_reader(file).unwrap()
+}
+
+fn save<T: Serialize>(name: &str, vectors: &[T]) {
+    let file = File::create(vector_path(name)).unwrap();
+    serde_json::to_writer_pretty(file, vectors).unwrap();
+}
+
+fn parse_metadata(hex: &str) -> u128 {
+    u128::from_str_radix(hex, 16).unwrap()
+}
+
+fn test_plot_id() -> PlotId {
+    let farmer_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+        .unwrap()
+        .public_key();
+    let pool_key = Ed25519PrivateKey::from_bytes(&[2; 32])
+        .unwrap()
+        .public_key();
+    PlotId::new(farmer_key, pool_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+}
+
+fn entries(fx: &[u64]) -> Vec<PlotEntry> {
+    fx.iter()
+        .map(|&fx| PlotEntry {
+            fx,
+            metadata: None,
+            position: None,
+            offset: None,
+        })
+        .collect()
+}
+
+/// Proofs of the plot sorted by x values
+fn plot_proofs(prover: &Prover, challenge: &[u8]) -> Vec<ProofVector> {
+    let verifier = Verifier::new().with_plot_filter(0);
+    let mut proofs = prover
+        .retrieve_all_proofs(challenge, &[])
+        .unwrap()
+        .iter()
+        .map(|proof| ProofVector {
+            x_values: proof.x_values.clone(),
+            quality: verifier.get_quality_string(proof).unwrap(),
+        })
+        .collect::<Vec<ProofVector>>();
+    proofs.sort_by(|a, b| a.x_values.cmp(&b.x_values));
+    proofs
+}
+
+fn challenge(k: usize, i: u32) -> Vec<u8> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&(k as u32).to_le_bytes());
+    hasher.update(&i.to_le_bytes());
+    hasher.finalize().as_bytes().to_vec()
+}
+
+#[test]
+fn test_f1_vectors() {
+    let vectors = load::<F1Vector>("f1.json");
+    assert!(!vectors.is_empty());
+    for vector in vectors {
+        let f1 = F1Calculator::new(vector.k, vector.plot_seed);
+        let bits = f1
+            .calculate_f1(&BitsWrapper::from(vector.x, vector.k))
+            .unwrap();
+        assert_eq!(
+            from_bits(&bits),
+            vector.f1,
+            "k = {}, x = {}",
+            vector.k,
+            vector.x
+        );
+        assert_eq!(f1.calculate_f1_range(vector.x, 1), vec![vector.f1]);
+    }
+}
+
+#[test]
+fn test_fx_vectors() {
+    let vectors = load::<FxVector>("fx.json");
+    assert!(!vectors.is_empty());
+    for vector in vectors {
+        let fx = FxCalculator::new(vector.k, vector.table_index);
+        let size = collation_size_bits(vector.table_index, vector.k);
+        let (left, right) = (parse_metadata(&vector.left), parse_metadata(&vector.right));
+        let expected = (vector.fx, parse_metadata(&vector.metadata));
+
+        assert_eq!(fx.calculate_fn_int(vector.y, left, right), expected);
+        let (y, metadata) = fx.calculate_fn(
+            &to_bits(vector.y, vector.k + PARAM_EXT),
+            &to_bits_u128(left, size),
+            &to_bits_u128(right, size),
+        );
+        assert_eq!((from_bits(&y), from_bits_u128(&metadata)), expected);
+    }
+}
+
+#[test]
+fn test_matching_vectors() {
+    let vectors = load::<MatchingVector>("matching.json");
+    assert!(!vectors.is_empty());
+    let mut fx = FxCalculator::new(12, 2);
+    for vector in vectors {
+        let matches = fx
+            .find_matches(&entries(&vector.left), &entries(&vector.right))
+            .into_iter()
+            .map(|m| (m.left_index, m.right_index))
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(matches, vector.matches);
+    }
+}
+
+#[test]
+fn test_proof_vectors() {
+    let vectors = load::<PlotVector>("proofs.json");
+    assert!(!vectors.is_empty());
+    let verifier = Verifier::new().with_plot_filter(0);
+
+    // The proofs are checked without the plots first
+    for vector in &vectors {
+        for expected in &vector.proofs {
+            let proof = Proof {
+                x_values: expected.x_values.clone(),
+                challenge: vector.challenge.clone(),
+                signage_data: Vec::new(),
+                k: vector.k,
+                plot_id: PlotId::try_from_slice(&vector.plot_id).unwrap(),
+            };
+            assert_eq!(
+                verifier.get_quality_string(&proof).unwrap(),
+                expected.quality
+            );
+        }
+    }
+
+    // Then the plots are created again and must give the same proofs
+    for k in PROOF_K {
+        let plot_vectors = vectors.iter().filter(|v| v.k == k).collect::<Vec<_>>();
+        assert!(!plot_vectors.is_empty());
+        let plot_id = PlotId::try_from_slice(&plot_vectors[0].plot_id).unwrap();
+        let dir = TempDir::new("spaceframe_vectors").unwrap();
+        let pos = PoSpace::new(k, plot_id, dir.path()).unwrap();
+        pos.plot().unwrap();
+        let prover = Prover::new(pos).with_plot_filter(0);
+
+        for vector in plot_vectors {
+            let proofs = plot_proofs(&prover, &vector.challenge);
+            assert_eq!(proofs.len(), vector.proofs.len(), "k = {}", k);
+            for (proof, expected) in proofs.iter().zip(&vector.proofs) {
+                assert_eq!(proof.x_values, expected.x_values, "k = {}", k);
+                assert_eq!(proof.quality, expected.quality, "k = {}", k);
+            }
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn generate_vectors() {
+    let mut f1_vectors = Vec::new();
+    for k in [12, 14, 17, 20, 25, 32] {
+        let f1 = F1Calculator::new(k, PLOT_SEED);
+        let last = (1u64 << k) - 1;
+        for x in [0, 1, 2, 3, 37, 1000, last / 3, last / 2, last - 1, last] {
+            let f1_value = from_bits(&f1.calculate_f1(&BitsWrapper::from(x, k)).unwrap());
+            f1_vectors.push(F1Vector {
+                k,
+                plot_seed: PLOT_SEED,
+                x,
+                f1: f1_value,
+            });
+        }
+    }
+    save("f1.json", &f1_vectors);
+
+    let mut fx_vectors = Vec::new();
+    for k in [12, 14, 20, 32] {
+        for table_index in 2..=7 {
+            let fx = FxCalculator::new(k, table_index);
+            let size = collation_size_bits(table_index, k);
+            for i in 0..4u64 {
+                // Deterministic inputs spread over the whole range of values
+                let hash = blake3::hash(&[k as u8, table_index as u8, i as u8]);
+                let bytes = hash.as_bytes();
+                let y = u64::from_le_bytes(bytes[..8].try_into().unwrap()) >> (64 - k - PARAM_EXT);
+                let left = u128::from_le_bytes(bytes[..16].try_into().unwrap()) >> (128 - size);
+                let right = u128::from_le_bytes(bytes[16..].try_into().unwrap()) >> (128 - size);
+                let (fx_value, metadata) = fx.calculate_fn_int(y, left, right);
+                fx_vectors.push(FxVector {
+                    k,
+                    table_index,
+                    y,
+                    left: format!("{:x}", left),
+                    right: format!("{:x}", right),
+                    fx: fx_value,
+                    metadata: format!("{:x}", metadata),
+                });
+            }
+        }
+    }
+    save("fx.json", &fx_vectors);
+
+    // Adjacent buckets of the f1 values of a small table
+    let mut fx_values = F1Calculator::new(12, PLOT_SEED).calculate_f1_range(0, 1 << 12);
+    fx_values.sort_unstable();
+    let mut matching_vectors = Vec::new();
+    let mut fx = FxCalculator::new(12, 2);
+    for bucket in 0..3 {
+        let in_bucket = |b: u64| {
+            fx_values
+                .iter()
+                .copied()
+                .filter(|fx| fx / PARAM_BC == b)
+                .collect::<Vec<u64>>()
+        };
+        let (left, right) = (in_bucket(bucket), in_bucket(bucket + 1));
+        let matches = fx
+            .find_matches(&entries(&left), &entries(&right))
+            .into_iter()
+            .map(|m| (m.left_index, m.right_index))
+            .collect();
+        matching_vectors.push(MatchingVector {
+            left,
+            right,
+            matches,
+        });
+    }
+    save("matching.json", &matching_vectors);
+
+    let mut plot_vectors = Vec::new();
+    let plot_id = test_plot_id();
+    for k in PROOF_K {
+        let dir = TempDir::new("spaceframe_vectors").unwrap();
+        let pos = PoSpace::new(k, plot_id, dir.path()).unwrap();
+        pos.plot().unwrap();
+        let prover = Prover::new(pos).with_plot_filter(0);
+
+        // The first challenges having proofs, and one without any
+        let mut empty = false;
+        let mut i = 0;
+        while plot_vectors
+            .iter()
+            .filter(|v: &&PlotVector| v.k == k)
+            .count()
+            < CHALLENGES_PER_PLOT
+        {
+            let challenge = challenge(k, i);
+            i += 1;
+            let proofs = plot_proofs(&prover, &challenge);
+            if proofs.is_empty() && empty {
+                continue;
+            }
+            empty |= proofs.is_empty();
+            plot_vectors.push(PlotVector {
+                k,
+                plot_id: plot_id.try_to_vec().unwrap(),
+                challenge,
+                proofs,
+            });
+        }
+    }
+    save("proofs.json", &plot_vectors);
+}
@@ -0,0 +1,201 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use spaceframe_crypto::ed25519::Ed25519PrivateKey;
+use spaceframe_crypto::traits::PrivateKey;
+use spaceframe_pospace::core::PoSpace;
+use spaceframe_pospace::error::VerifierError;
+use spaceframe_pospace::plot_id::PlotId;
+use spaceframe_pospace::proofs::{Proof, Prover};
+use spaceframe_pospace::verifier::{canonical_x_values, Verifier};
+use tempdir::TempDir;
+
+const TEST_K: usize = 12;
+
+fn test_plot_id() -> PlotId {
+    let farmer_key = Ed25519PrivateKey::from_bytes(&[1; 32])
+        .unwrap()
+        .public_key();
+    let pool_key = Ed25519PrivateKey::from_bytes(&[2; 32])
+        .unwrap()
+        .public_key();
+    PlotId::new(farmer_key, pool_key, *b"aaaabbbbccccddddaaaabbbbccccdddd")
+}
+
+/// A valid proof of a small plot, with the plot filter disabled
+fn valid_proof() -> Proof {
+    let dir = TempDir::new("spaceframe_verifier").unwrap();
+    let pos = PoSpace::new(TEST_K, test_plot_id(), dir.path()).unwrap();
+    pos.plot().unwrap();
+    let prover = Prover::new(pos).with_plot_filter(0);
+    let challenge = [
+        245, 160, 73, 48, 89, 115, 104, 85, 10, 216, 202, 121, 167, 250, 147, 117, 216, 161, 24,
+        31, 92, 127, 168, 209, 117, 30, 36, 143, 20, 107, 229, 71,
+    ];
+    let mut proofs = prover.retrieve_all_proofs(&challenge, &[]).unwrap();
+    assert_eq!(proofs.len(), 1);
+    let proof = proofs.remove(0);
+    assert_eq!(
+        canonical_x_values(TEST_K, proof.plot_id.plot_seed(), &proof.x_values).unwrap(),
+        proof.x_values
+    );
+    proof
+}
+
+fn verifier_error(proof: &Proof) -> VerifierError {
+    let err = Verifier::new()
+        .with_plot_filter(0)
+        .verify_proof(proof)
+        .expect_err("The proof must be rejected");
+    err.downcast::<VerifierError>()
+        .expect("The error must be a VerifierError")
+}
+
+#[test]
+fn test_verify_invalid_proofs() {
+    let proof = valid_proof();
+    let verifier = Verifier::new().with_plot_filter(0);
+    assert!(verifier.verify_proof(&proof).is_ok());
+
+    for k in [0, 11, 33, 60, usize::MAX] {
+        let mut invalid = proof.clone();
+        invalid.k = k;
+        assert!(matches!(verifier_error(&invalid), VerifierError::InvalidK(found) if found == k));
+    }
+    assert_eq!(
+        VerifierError::InvalidK(60).to_string(),
+        "Space parameter k must be between 12 and 32, found 60"
+    );
+
+    let mut invalid = proof.clone();
+    invalid.x_values[7] = 1 << TEST_K;
+    assert!(matches!(
+        verifier_error(&invalid),
+        VerifierError::XValueOutOfRange(x) if x == 1 << TEST_K
+    ));
+
+    let mut invalid = proof.clone();
+    invalid.x_values.truncate(63);
+    assert!(matches!(
+        verifier_error(&invalid),
+        VerifierError::InvalidXValuesCount(63)
+    ));
+
+    let mut invalid = proof.clone();
+    invalid.x_values.push(0);
+    assert!(matches!(
+        verifier_error(&invalid),
+        VerifierError::InvalidXValuesCount(65)
+    ));
+
+    let mut invalid = proof.clone();
+    invalid.challenge.truncate(31);
+    assert!(matches!(
+        verifier_error(&invalid),
+        VerifierError::InvalidChallengeLength(31)
+    ));
+
+    // The subtrees of an entry are swapped at every level
+    for table_index in 2..=7 {
+        let subtree_size = 1 << (table_index - 2);
+        let mut invalid = proof.clone();
+        invalid.x_values[..2 * subtree_size].rotate_left(subtree_size);
+        assert!(matches!(
+            verifier_error(&invalid),
+            VerifierError::NotCanonical(found) if found == table_index
+        ));
+        let canonical =
+            canonical_x_values(TEST_K, proof.plot_id.plot_seed(), &invalid.x_values).unwrap();
+        assert_eq!(canonical, proof.x_values);
+    }
+
+    let mut invalid = proof.clone();
+    invalid.x_values[5] ^= 1;
+    assert!(matches!(
+        verifier_error(&invalid),
+        VerifierError::NotMatching(_) | VerifierError::NotCanonical(_)
+    ));
+
+    let mut invalid = proof.clone();
+    invalid.challenge[0] ^= 1;
+    assert!(matches!(
+        verifier_error(&invalid),
+        VerifierError::ChallengeMismatch
+    ));
+}
+
+#[test]
+fn test_verify_random_proofs() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let verifier = Verifier::new().with_plot_filter(0);
+    let plot_id = test_plot_id();
+
+    for i in 0..300 {
+        // Most proofs have a valid structure, so that they go through the matching
+        let k = if i % 4 == 0 {
+            rng.gen_range(0..80)
+        } else {
+            rng.gen_range(12..=32)
+        };
+        let x_count = if i % 8 == 0 {
+            rng.gen_range(0..130)
+        } else {
+            64
+        };
+        let challenge_size = if i % 8 == 1 { rng.gen_range(0..64) } else { 32 };
+        let x_values = (0..x_count)
+            .map(|_| {
+                if i % 2 == 0 || k >= 64 {
+                    rng.gen()
+                } else {
+                    rng.gen_range(0..1u64 << k)
+                }
+            })
+            .collect();
+        let proof = Proof {
+            x_values,
+            challenge: (0..challenge_size).map(|_| rng.gen()).collect(),
+            signage_data: (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect(),
+            k,
+            plot_id,
+        };
+        assert!(verifier.verify_proof(&proof).is_err(), "{:?}", proof);
+        assert!(verifier.get_quality_string(&proof).is_err());
+    }
+}
+
+#[test]
+fn test_verify_batch() {
+    let proof = valid_proof();
+    let verifier = Verifier::new().with_plot_filter(0);
+    assert!(verifier.verify_batch(&[]).is_empty());
+
+    let mut swapped = proof.clone();
+    swapped.x_values.swap(0, 1);
+    let mut invalid_k = proof.clone();
+    invalid_k.k = 60;
+    let mut other_k = proof.clone();
+    other_k.k = TEST_K + 1;
+    let mut other_challenge = proof.clone();
+    other_challenge.challenge[0] ^= 1;
+    let proofs = [
+        proof.clone(),
+        swapped,
+        invalid_k,
+        other_k,
+        proof,
+        other_challenge,
+    ];
+
+    let results = verifier.verify_batch(&proofs);
+    assert_eq!(results.len(), proofs.len());
+    for (result, proof) in results.iter().zip(&proofs) {
+        assert_eq!(
+            result.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            verifier.verify_proof(proof).map_err(|e| e.to_string())
+        );
+    }
+    assert_eq!(
+        results.iter().map(|r| r.is_ok()).collect::<Vec<bool>>(),
+        vec![true, false, false, false, true, false]
+    );
+}